@@ -0,0 +1,200 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use anyhow::Context;
+use clap::{error::ErrorKind, ArgMatches, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+use crate::parser::Arguments;
+use crate::template::nearest;
+
+pub const CONFIG_FILE_NAME: &str = "lconvert.toml";
+const BUILTIN_PRESETS: &str = include_str!("presets.toml");
+
+/// Contents of 'lconvert.toml'
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub presets: HashMap<String, Preset>,
+}
+
+/// Named argument values, e.g. `[presets.web-video]`. Arguments given on the command line take precedence
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Preset {
+    /// Shown by 'lconvert presets'
+    pub description: Option<String>,
+    /// File the preset was read from, `None` for built-in presets
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// '-m', e.g. 'mov=mp4[-crf 23],png=webp'
+    pub extension_map: Option<String>,
+    /// '-o'
+    pub output: Option<String>,
+    /// ffmpeg options given after '--'
+    pub options: Option<Vec<String>>,
+    /// '--opts', e.g. `["mp3:-b:a 192k"]`
+    pub opts: Option<Vec<String>>,
+    /// '--input-opts'
+    pub input_opts: Option<Vec<String>>,
+    /// '--template'
+    pub template: Option<Vec<String>>,
+    /// '--cmd'
+    pub cmd: Option<Vec<String>>,
+    /// '-n'
+    pub n_subprocesses: Option<u32>,
+    /// '--n-probes'
+    pub n_probes: Option<u32>,
+    /// '-y'
+    pub allow_override: Option<bool>,
+    /// '--skip-existing'
+    pub skip_existing: Option<bool>,
+    /// '--update'
+    pub update: Option<bool>,
+    /// '-c'
+    pub case_sensitive: Option<bool>,
+    /// '-d'
+    pub disable_pattern_append: Option<bool>,
+    /// '--tmp-dir'
+    pub tmp_dir: Option<PathBuf>,
+}
+
+impl Config {
+    /// Built-in presets, then 'lconvert.toml' of the user ('~/.config/lconvert/'), then of the current directory.
+    /// Later presets replace earlier ones with the same name
+    pub fn load() -> Result<Self, anyhow::Error> {
+        let mut config = Self::builtin();
+
+        for path in config_paths().iter().filter(|x| x.is_file()) {
+            config.presets.extend(Self::from_file(path)?.presets);
+        }
+        Ok(config)
+    }
+
+    /// Presets that come with lconvert (see 'src/presets.toml')
+    pub fn builtin() -> Self {
+        toml::from_str(BUILTIN_PRESETS).expect("Built-in presets are valid")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file: '{}'", path.display()))?;
+        let mut config: Self = toml::from_str(&text)
+            .with_context(|| format!("Invalid config file: '{}'", path.display()))?;
+
+        for preset in config.presets.values_mut() {
+            preset.path = Some(path.to_owned());
+        }
+        Ok(config)
+    }
+
+    pub fn preset(&self, name: &str) -> Result<&Preset, anyhow::Error> {
+        if let Some(preset) = self.presets.get(name) {
+            return Ok(preset);
+        }
+        let mut names: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+        names.sort_unstable();
+
+        match nearest(name, &names) {
+            Some(nearest) => anyhow::bail!("Unknown preset '{name}', did you mean '{nearest}'?"),
+            None if names.is_empty() => anyhow::bail!("Unknown preset '{name}', no presets found in: {}", config_paths()
+                .iter()
+                .map(|x| format!("'{}'", x.display()))
+                .collect::<Vec<_>>()
+                .join(", ")),
+            None => anyhow::bail!("Unknown preset '{name}', expected one of: {}", names.join(", ")),
+        }
+    }
+}
+
+impl Preset {
+    /// Makes the values of the preset defaults of the arguments `command` has.
+    /// Flags turned off in `given` (e.g. '--no-allow-override') are left alone
+    pub fn apply(&self, mut command: clap::Command, given: &ArgMatches) -> clap::Command {
+        for (id, _, values) in self.arguments() {
+            let Some(values) = values else { continue };
+            if values.is_empty() && given.try_get_one::<bool>(&format!("no_{id}")).ok().flatten() == Some(&true) {
+                continue;
+            }
+            if command.get_arguments().any(|x| x.get_id() == id) {
+                command = command.mut_arg(id, |arg| match values.is_empty() {
+                    true => arg.default_value("true").required(false),
+                    false => arg.default_values(values).required(false),
+                });
+            }
+        }
+        command
+    }
+
+    /// Command line arguments with the same values, e.g. `["-m", "wav=mp3", "--", "-b:a", "192k"]`
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        for (_, name, values) in self.arguments() {
+            let Some(values) = values else { continue };
+            if values.is_empty() || name == "--" {
+                args.push(name.to_owned());
+                args.extend(values);
+            } else {
+                args.extend(values.into_iter().flat_map(|x| [name.to_owned(), x]));
+            }
+        }
+        args
+    }
+
+    /// Argument id, how it is written on the command line and the values of the preset for it.
+    /// Flags that are set have no values, options after '--' come last
+    fn arguments(&self) -> [(&'static str, &'static str, Option<Vec<String>>); 15] {
+        let flag = |x: Option<bool>| x.filter(|x| *x).map(|_| Vec::new());
+
+        [
+            ("extension_map", "-m", self.extension_map.clone().map(|x| vec![x])),
+            ("output", "-o", self.output.clone().map(|x| vec![x])),
+            ("extension_options", "--opts", self.opts.clone()),
+            ("input_options", "--input-opts", self.input_opts.clone()),
+            ("command_templates", "--template", self.template.clone()),
+            ("converter_commands", "--cmd", self.cmd.clone()),
+            ("n_subprocesses", "-n", self.n_subprocesses.map(|x| vec![x.to_string()])),
+            ("n_probes", "--n-probes", self.n_probes.map(|x| vec![x.to_string()])),
+            ("allow_override", "-y", flag(self.allow_override)),
+            ("skip_existing", "--skip-existing", flag(self.skip_existing)),
+            ("update", "--update", flag(self.update)),
+            ("case_sensitive", "-c", flag(self.case_sensitive)),
+            ("disable_pattern_append", "-d", flag(self.disable_pattern_append)),
+            ("tmp_dir", "--tmp-dir", self.tmp_dir.as_ref().map(|x| vec![x.to_string_lossy().into_owned()])),
+            ("FFMPEG_OPTIONS", "--", self.options.clone()),
+        ]
+    }
+}
+
+/// Where 'lconvert.toml' is looked for, in order of increasing precedence
+pub fn config_paths() -> Vec<PathBuf> {
+    let user_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|x| x.is_absolute())
+        .or_else(|| std::env::home_dir().map(|x| x.join(".config")));
+
+    user_dir
+        .map(|x| x.join("lconvert").join(CONFIG_FILE_NAME))
+        .into_iter()
+        .chain(std::iter::once(PathBuf::from(CONFIG_FILE_NAME)))
+        .collect()
+}
+
+/// Parses the command line like [`clap::Parser::parse`], using the values of '--preset' as defaults.
+/// Exits with usage errors (including an unknown preset or an invalid config file)
+pub fn parse_arguments() -> Arguments {
+    let mut command = Arguments::command();
+
+    // Only to find '--preset', the real parse below reports errors
+    let matches = Arguments::command().ignore_errors(true).get_matches();
+    let convert_matches = matches.subcommand_matches("sync").unwrap_or(&matches);
+    if let Some(name) = convert_matches.try_get_one::<String>("preset").ok().flatten() {
+        let preset = match Config::load().and_then(|x| x.preset(name).cloned()) {
+            Ok(preset) => preset,
+            Err(err) => command.error(ErrorKind::InvalidValue, format!("{err:#}")).exit(),
+        };
+        command = preset.apply(command, &matches).mut_subcommand("sync", |x| preset.apply(x, convert_matches));
+    }
+
+    let matches = command.get_matches();
+    Arguments::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+}
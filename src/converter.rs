@@ -0,0 +1,184 @@
+use std::{ffi::OsString, fmt::Debug, path::{Path, PathBuf}, sync::Arc};
+use anyhow::Context;
+use crate::ffmpeg::{assert_exists, FFMPEG_PATH};
+use crate::media::MediaInfo;
+use crate::parser::OutputPattern;
+use crate::planner::Job;
+
+/// Converts the input of a job into a file. Picked per extension map rule ('docx=pdf@soffice'), ffmpeg by default
+pub trait Converter: Debug + Send + Sync {
+    /// Name used in the extension map
+    fn name(&self) -> &'static str;
+
+    /// Program and arguments that convert the input of `job` into `output`, the temporary file
+    fn command(&self, job: &Job, output: &Path) -> (PathBuf, Vec<OsString>);
+
+    /// File the program actually writes for `output`, moved to the output file once it succeeds
+    fn written_file(&self, _job: &Job, output: &Path) -> PathBuf {
+        output.to_owned()
+    }
+
+    /// Arguments with {{input}} and {{output}} the planner fills placeholders of and stores in the job
+    fn command_template(&self) -> Option<&[String]> {
+        None
+    }
+
+    /// Media info of the input, for the duration shown by the progress bar
+    fn probe(&self, _input_file: &Path) -> Option<MediaInfo> {
+        None
+    }
+
+    /// Seconds of the input converted so far, from a line the program printed to stdout
+    fn parse_progress(&self, _line: &str) -> Option<u64> {
+        None
+    }
+}
+
+pub const CONVERTER_NAMES: [&str; 5] = ["ffmpeg", "magick", "pandoc", "soffice", "cmd"];
+
+/// Converter for a name of the extension map. `cmd` is the command template of '--cmd' for the rule
+pub fn converter_by_name(name: &str, cmd: Option<&[String]>) -> Result<Arc<dyn Converter>, anyhow::Error> {
+    Ok(match name {
+        "ffmpeg" => Arc::new(FFmpegConverter),
+        "magick" => Arc::new(MagickConverter { path: assert_exists(Path::new("magick"))? }),
+        "pandoc" => Arc::new(PandocConverter { path: assert_exists(Path::new("pandoc"))? }),
+        "soffice" => Arc::new(SofficeConverter { path: assert_exists(Path::new("soffice"))? }),
+        "cmd" => {
+            let args = cmd.context("Converter 'cmd' needs a command, see '--cmd'")?;
+            let program = args.first().context("Command of '--cmd' is empty")?;
+            assert_exists(Path::new(program))?;
+            Arc::new(CommandConverter { args: args.to_vec() })
+        },
+        _ => anyhow::bail!("Unknown converter '{name}', expected one of: {}", CONVERTER_NAMES.join(", ")),
+    })
+}
+
+/// ffmpeg, with options before '-i', after it or a command template (see [`Job::get_args`])
+#[derive(Debug)]
+pub struct FFmpegConverter;
+
+impl Converter for FFmpegConverter {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn command(&self, job: &Job, output: &Path) -> (PathBuf, Vec<OsString>) {
+        (FFMPEG_PATH.get().expect("Initialized with init_ffmpeg_paths").to_path_buf(), job.get_args(output))
+    }
+
+    fn probe(&self, input_file: &Path) -> Option<MediaInfo> {
+        MediaInfo::probe(input_file).ok()
+    }
+
+    /// Reads 'out_time_ms' of '-progress -'
+    fn parse_progress(&self, line: &str) -> Option<u64> {
+        let (metric, value) = line.trim().split_once('=')?;
+        if metric.trim() != "out_time_ms" {
+            return None;
+        }
+        Some(value.trim().parse::<u64>().ok()? / 1_000_000)
+    }
+}
+
+/// ImageMagick: `magick [INPUT_OPTIONS] INPUT [OPTIONS] OUTPUT`
+#[derive(Debug)]
+pub struct MagickConverter {
+    pub path: PathBuf,
+}
+
+impl Converter for MagickConverter {
+    fn name(&self) -> &'static str {
+        "magick"
+    }
+
+    fn command(&self, job: &Job, output: &Path) -> (PathBuf, Vec<OsString>) {
+        let mut args: Vec<OsString> = job.input_options.iter().map(OsString::from).collect();
+        args.push(job.input_file.clone().into());
+        args.extend(job.str_options.iter().map(OsString::from));
+        args.push(output.into());
+        (self.path.clone(), args)
+    }
+}
+
+/// pandoc: `pandoc INPUT [OPTIONS] -o OUTPUT`
+#[derive(Debug)]
+pub struct PandocConverter {
+    pub path: PathBuf,
+}
+
+impl Converter for PandocConverter {
+    fn name(&self) -> &'static str {
+        "pandoc"
+    }
+
+    fn command(&self, job: &Job, output: &Path) -> (PathBuf, Vec<OsString>) {
+        let mut args: Vec<OsString> = job.input_options.iter().map(OsString::from).collect();
+        args.push(job.input_file.clone().into());
+        args.extend(job.str_options.iter().map(OsString::from));
+        args.extend(["-o".into(), output.into()]);
+        (self.path.clone(), args)
+    }
+}
+
+/// LibreOffice: `soffice --headless [OPTIONS] --convert-to OUT_EXT --outdir DIR INPUT`.
+/// It names the output after the input, so it writes into a temporary directory next to the temporary file
+#[derive(Debug)]
+pub struct SofficeConverter {
+    pub path: PathBuf,
+}
+
+impl Converter for SofficeConverter {
+    fn name(&self) -> &'static str {
+        "soffice"
+    }
+
+    fn command(&self, job: &Job, output: &Path) -> (PathBuf, Vec<OsString>) {
+        let mut args: Vec<OsString> = vec!["--headless".into()];
+        args.extend(job.str_options.iter().map(OsString::from));
+        args.extend(["--convert-to".into(), output.extension().unwrap_or_default().to_owned()]);
+        args.extend(["--outdir".into(), output.with_extension("d").into()]);
+        args.push(job.input_file.clone().into());
+        (self.path.clone(), args)
+    }
+
+    fn written_file(&self, job: &Job, output: &Path) -> PathBuf {
+        let mut written = output.with_extension("d").join(job.input_file.file_stem().unwrap_or_default());
+        written.set_extension(output.extension().unwrap_or_default());
+        written
+    }
+}
+
+/// Any program, from a command template with {{input}} and {{output}} ('--cmd')
+#[derive(Debug)]
+pub struct CommandConverter {
+    /// Program and arguments, placeholders other than {{input}} and {{output}} are filled when planning
+    pub args: Vec<String>,
+}
+
+impl Converter for CommandConverter {
+    fn name(&self) -> &'static str {
+        "cmd"
+    }
+
+    fn command_template(&self) -> Option<&[String]> {
+        Some(&self.args)
+    }
+
+    fn command(&self, job: &Job, output: &Path) -> (PathBuf, Vec<OsString>) {
+        let args = job.command_template.as_deref().unwrap_or(&self.args);
+        let mut args = args.iter().map(|x| fill_markers(x, &job.input_file, output));
+        (args.next().map(PathBuf::from).unwrap_or_default(), args.collect())
+    }
+}
+
+/// Replaces {{input}} and {{output}}, an argument that is only a marker keeps a non utf-8 path as it is
+pub fn fill_markers(arg: &str, input_file: &Path, output: &Path) -> OsString {
+    match arg {
+        OutputPattern::INPUT => input_file.into(),
+        OutputPattern::OUTPUT => output.into(),
+        _ => arg
+            .replace(OutputPattern::INPUT, &input_file.to_string_lossy())
+            .replace(OutputPattern::OUTPUT, &output.to_string_lossy())
+            .into(),
+    }
+}
@@ -1,64 +1,199 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
 use std::fmt::Display;
-use std::io::Error;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{Error, ErrorKind};
 use std::path::{PathBuf, Path};
-use std::process::{Child, Output, Stdio};
-use std::str::from_utf8;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
 use anyhow::Context;
 use which::which;
-use std::sync::OnceLock;
+use crate::converter::{fill_markers, Converter, FFmpegConverter};
+use crate::media::MediaInfo;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 pub static FFMPEG_PATH: OnceLock<&Path> = OnceLock::new();
 pub static FFPROBE_PATH: OnceLock<&Path> = OnceLock::new();
 
 #[derive(Debug)]
+/// Variants that describe the process carry the name of its converter, e.g. 'ffmpeg'
 pub enum FFmpegError<'a> {
-    ChildError(&'a Error),
-    OutputError(&'a str),
+    ChildError(&'static str, &'a Error),
+    OutputError(&'static str, ExitStatus, Cow<'a, str>),
+    /// ffmpeg succeeded but the temporary file could not be moved to the output file
+    RenameError(&'a Error),
 }
 
-impl<'a> Display for FFmpegError<'a> {
+impl Display for FFmpegError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { match self {
-        FFmpegError::ChildError(child_err) => {
-            writeln!(f, "Failed to execute ffmpeg: {child_err}") 
+        FFmpegError::ChildError(name, child_err) => {
+            writeln!(f, "Failed to execute {name}: {child_err}") 
         },
-        FFmpegError::OutputError(output_err) => {
-            let width = output_err.split('\n')
-                .reduce(|acc, x| if x.len() > acc.len() { x } else { acc })
-                .unwrap_or("")
-                .len()
-                .saturating_sub(3);
-
-            writeln!(f, "+{:-^1$}+", " Begin ffmpeg stderr ", width)?;
-            writeln!(f, "{}", output_err.trim_end())?;
-            write!(f, "+{:-^1$}+", " End ffmpeg stderr ", width)
+        FFmpegError::OutputError(name, status, output_err) => {
+            if output_err.is_empty() {
+                write!(f, "{name} failed with {status}")
+            } else {
+                write_stderr_box(f, name, output_err)
+            }
+        },
+        FFmpegError::RenameError(rename_err) => {
+            write!(f, "Failed to move temporary file to the output file: {rename_err}")
         },
     }}
 }
 
+/// Outcome of a finished ffmpeg process, decided by its exit status
 #[derive(Debug)]
+pub enum FFmpegStatus<'a> {
+    Success,
+    /// ffmpeg exited successfully but printed something to stderr
+    Warning(Cow<'a, str>),
+    Failure(FFmpegError<'a>),
+}
+
+impl FFmpegStatus<'_> {
+    pub fn as_str(&self) -> &'static str { match self {
+        FFmpegStatus::Success => "success",
+        FFmpegStatus::Warning(_) => "warning",
+        FFmpegStatus::Failure(_) => "failure",
+    }}
+}
+
+fn write_stderr_box(f: &mut std::fmt::Formatter<'_>, name: &str, stderr: &str) -> std::fmt::Result {
+    let width = stderr.split('\n')
+        .reduce(|acc, x| if x.len() > acc.len() { x } else { acc })
+        .unwrap_or("")
+        .len()
+        .saturating_sub(3);
+
+    writeln!(f, "+{:-^1$}+", format!(" Begin {name} stderr "), width)?;
+    writeln!(f, "{}", stderr.trim_end())?;
+    write!(f, "+{:-^1$}+", format!(" End {name} stderr "), width)
+}
+
+/// Displays captured stderr of a process that succeeded with warnings, with the name of its converter
+pub struct FFmpegWarning<'a>(pub &'static str, pub &'a str);
+
+impl Display for FFmpegWarning<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_stderr_box(f, self.0, self.1)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct FFmpegOptions {
     pub input_file: PathBuf,
     pub output_file: PathBuf,
+    /// Extension map rule that selected this file, e.g. 'mp3=wav' or '*=wav'
+    pub rule: String,
     pub allow_override: bool,
+    /// Directory for the temporary file ffmpeg writes to, next to the output file if `None`
+    pub tmp_dir: Option<PathBuf>,
     pub duration: Option<f64>,
+    /// What ffprobe found out about the input, `None` until probed or if ffprobe failed
+    pub media_info: Option<MediaInfo>,
+    /// Options placed before '-i'
+    pub input_options: Vec<String>,
     pub str_options: Vec<String>,
+    /// Arguments replacing the input and every option, with {{input}} and {{output}} markers
+    pub command_template: Option<Vec<String>>,
+    /// Program that converts the input, ffmpeg unless the extension map rule picked another one
+    pub converter: Arc<dyn Converter>,
 }
 
 impl FFmpegOptions {
-    pub fn new(input_file: PathBuf, output_file: PathBuf, allow_override: bool, options: Vec<String>) -> Self {
-        let duration = get_duration(&input_file).unwrap_or(None); 
-
+    /// The input is not probed yet, see [`FFmpegOptions::probe`]
+    pub fn new(input_file: PathBuf, output_file: PathBuf, rule: String, allow_override: bool, options: Vec<String>) -> Self {
         Self { 
             input_file, 
             output_file, 
+            rule,
             allow_override, 
-            duration,
+            tmp_dir: None,
+            duration: None,
+            media_info: None,
+            input_options: Vec::new(),
             str_options: options, 
+            command_template: None,
+            converter: Arc::new(FFmpegConverter),
+        }
+    }
+
+    /// Probes the input with the converter (ffprobe for ffmpeg).
+    /// Duration stays `None` if it has none (e.g. an image), probing failed or the converter can not probe
+    pub fn probe(&mut self) {
+        self.set_media_info(self.converter.probe(&self.input_file));
+    }
+
+    /// Sets media info probed elsewhere, and the duration from it
+    pub fn set_media_info(&mut self, media_info: Option<MediaInfo>) {
+        self.duration = media_info.as_ref().and_then(|x| x.duration);
+        self.media_info = media_info;
+    }
+
+    /// File ffmpeg writes to, renamed to the output file once ffmpeg succeeds.
+    /// Keeps the output extension so ffmpeg still picks the right format.
+    /// Outputs are unique within a run, the process id keeps concurrent runs apart
+    pub fn get_tmp_file(&self) -> PathBuf {
+        let stem = self.output_file.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = self.output_file.extension().map(|x| format!(".{}", x.to_string_lossy())).unwrap_or_default();
+        let pid = std::process::id();
+
+        match &self.tmp_dir {
+            Some(tmp_dir) => {
+                // outputs from different directories may share a name
+                let mut hasher = DefaultHasher::new();
+                self.output_file.hash(&mut hasher);
+                tmp_dir.join(format!(".{stem}.{pid}.{:016x}.lconvert-tmp{extension}", hasher.finish()))
+            },
+            None => self.output_file.with_file_name(format!(".{stem}.{pid}.lconvert-tmp{extension}")),
+        }
+    }
+
+    /// Program and arguments the converter runs for this job
+    pub fn get_command(&self) -> (PathBuf, Vec<OsString>) {
+        self.converter.command(self, &self.get_tmp_file())
+    }
+
+    /// Arguments passed to ffmpeg for this job writing to `output` (without the executable)
+    pub fn get_args(&self, output: &Path) -> Vec<OsString> {
+        // ffmpeg may always override its own temporary file, existing outputs are checked before spawning
+        let mut args: Vec<OsString> = vec!["-hide_banner".into(), "-y".into()];
+        args.extend(["-loglevel", "error", "-progress", "-", "-nostats"].map(OsString::from));
+
+        if let Some(template) = &self.command_template {
+            args.extend(template.iter().map(|x| fill_markers(x, &self.input_file, output)));
+            return args;
+        }
+
+        args.extend(self.input_options.iter().map(OsString::from));
+        args.push("-i".into());
+        args.push(self.input_file.clone().into());
+        args.extend(self.str_options.iter().map(OsString::from));
+        args.push(output.into());
+        args
+    }
+
+    /// Removes what the converter wrote for the temporary file
+    pub fn remove_tmp_files(&self) {
+        let tmp_file = self.get_tmp_file();
+        let written_file = self.converter.written_file(self, &tmp_file);
+
+        for file in [&written_file, &tmp_file] {
+            if file.exists() {
+                let _ = std::fs::remove_file(file);
+            }
+        }
+        if written_file != tmp_file {
+            if let Some(dir) = written_file.parent() {
+                let _ = std::fs::remove_dir(dir);
+            }
         }
     }
 
     pub fn start(self) -> FFmpegProcessStarted {
         FFmpegProcessStarted {
+            start_time: Instant::now(),
             child: spawn_ffmpeg(&self),
             options: self,
         }
@@ -68,77 +203,165 @@ impl FFmpegOptions {
 pub struct FFmpegProcessStarted {
     pub child: Result<Child, Error>,
     pub options: FFmpegOptions,
+    pub start_time: Instant,
 }
 
 impl FFmpegProcessStarted {
+    /// Waits for ffmpeg, then moves the temporary file into place on success or removes it on failure
     pub fn finish(self) -> FFmpegProcessCompleted {
+        let output = match self.child {
+            Ok(child) => child.wait_with_output(),
+            Err(err) => Err(err),
+        };
+        Self::complete(self.options, self.start_time, output)
+    }
+
+    /// Like [`FFmpegProcessStarted::finish`], for when stdout and stderr were taken from the child and read elsewhere
+    pub fn finish_with_stderr(self, stderr: Vec<u8>) -> FFmpegProcessCompleted {
+        let output = match self.child {
+            Ok(child) => child.wait_with_output().map(|x| Output { stderr, ..x }),
+            Err(err) => Err(err),
+        };
+        Self::complete(self.options, self.start_time, output)
+    }
+
+    fn complete(options: FFmpegOptions, start_time: Instant, output: Result<Output, Error>) -> FFmpegProcessCompleted {
+        let written_file = options.converter.written_file(&options, &options.get_tmp_file());
+        let mut rename_error = None;
+
+        if output.as_ref().is_ok_and(|x| x.status.success()) {
+            rename_error = move_file(&written_file, &options.output_file).err();
+        }
+        options.remove_tmp_files();
+
         FFmpegProcessCompleted {
-            output: match self.child {
-                Ok(child) => child.wait_with_output(),
-                Err(err) => Err(err),
-            },
-            options: self.options,
+            output,
+            rename_error,
+            options,
+            wall_time: start_time.elapsed(),
+        }
+    }
+
+    /// Kills ffmpeg and removes the partially written temporary file
+    pub fn cancel(self) -> FFmpegOptions {
+        if let Ok(mut child) = self.child {
+            // wrappers such as 'sh -c' or soffice leave the conversion to processes of their own
+            kill_process_group(child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+            self.options.remove_tmp_files();
         }
+        self.options
     }
 }
 
 pub struct FFmpegProcessCompleted {
     pub output: Result<Output, Error>,
+    /// Error moving the temporary file to the output file
+    pub rename_error: Option<Error>,
     pub options: FFmpegOptions,
+    pub wall_time: Duration,
 }
 
 impl FFmpegProcessCompleted {
-    pub fn get_error(&self) -> Option<FFmpegError> {
-        if let Err(err) = &self.output {
-            return Some(FFmpegError::ChildError(err));
+    pub fn get_status(&self) -> FFmpegStatus<'_> {
+        let output = match &self.output {
+            Ok(output) => output,
+            Err(err) => return FFmpegStatus::Failure(FFmpegError::ChildError(self.options.converter.name(), err)),
+        };
+
+        if let Some(err) = &self.rename_error {
+            return FFmpegStatus::Failure(FFmpegError::RenameError(err));
         }
 
-        let error_message = from_utf8(
-            &self.output.as_ref().ok().unwrap().stderr
-        ).expect("Non utf-8 characters in output");
+        let stderr = match String::from_utf8_lossy(&output.stderr) {
+            Cow::Borrowed(s) => Cow::Borrowed(s.trim_end()),
+            Cow::Owned(s) => Cow::Owned(s.trim_end().to_owned()),
+        };
 
-        if error_message.is_empty() {
-            return None;
+        if !output.status.success() {
+            FFmpegStatus::Failure(FFmpegError::OutputError(self.options.converter.name(), output.status, stderr))
+        } else if !stderr.is_empty() {
+            FFmpegStatus::Warning(stderr)
         } else {
-            return Some(FFmpegError::OutputError(error_message.trim_end()));
+            FFmpegStatus::Success
         }
     }
-}
 
-pub fn get_duration(file_path: &PathBuf) -> Result<Option<f64>, anyhow::Error> {
-    let child = std::process::Command::new(FFPROBE_PATH.get().expect("Initialized this in main"))
-        .args(["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1"])
-        .arg(file_path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
+    pub fn get_error(&self) -> Option<FFmpegError<'_>> {
+        match self.get_status() {
+            FFmpegStatus::Failure(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    pub fn get_warning(&self) -> Option<Cow<'_, str>> {
+        match self.get_status() {
+            FFmpegStatus::Warning(warning) => Some(warning),
+            _ => None,
+        }
+    }
+}
 
-    let output = child.wait_with_output()?;
-    let output_str = from_utf8(&output.stdout)?.trim();
+/// Renames a file, falling back to copying when the destination is on another file system.
+/// The copy goes to a hidden file next to `to` first, so `to` never holds a partial copy
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
 
-    match output_str.parse::<f64>() {
-        Ok(duration) => { Ok(Some(duration))},
-        Err(_) => { Ok(None) },
+    let file_name = to.file_name().unwrap_or_default().to_string_lossy();
+    let copy = to.with_file_name(format!(".{file_name}.{}.lconvert-tmp", std::process::id()));
+    if let Err(err) = std::fs::copy(from, &copy).and_then(|_| std::fs::rename(&copy, to)) {
+        let _ = std::fs::remove_file(&copy);
+        return Err(err);
     }
+    std::fs::remove_file(from)
 }
 
 pub fn spawn_ffmpeg(options: &FFmpegOptions) -> Result<Child, Error> {
-    let child = std::process::Command::new(FFMPEG_PATH.get().expect("Initialized this in main"))
-        .arg("-hide_banner")
-        .arg(if options.allow_override {"-y"} else {"-n"})
-        .args(["-loglevel", "error", "-progress", "-", "-nostats"])
-        .arg("-i")
-        .arg(&options.input_file)
-        .args(options.str_options.iter())
-        .arg(&options.output_file)
+    if !options.allow_override && options.output_file.exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, format!("File '{}' already exists", options.output_file.display())));
+    }
+
+    let (program, args) = options.get_command();
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        // ffmpeg puts an interactive terminal into raw mode, keep it away from ours
+        .stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
-    Ok(child)
+        .stderr(Stdio::piped());
+
+    // Ctrl+C signals the whole foreground process group, the ctrlc handler decides when children stop
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    command.spawn()
+}
+
+/// Kills everything in the process group a child started by [`spawn_ffmpeg`] leads
+#[cfg(unix)]
+pub fn kill_process_group(pid: u32) {
+    unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
 }
 
+/// Children are not put into process groups on this platform, see [`FFmpegProcessStarted::cancel`]
+#[cfg(not(unix))]
+pub fn kill_process_group(_pid: u32) {}
+
 pub fn assert_exists(executable: &Path) -> Result<PathBuf, anyhow::Error> {
-    Ok(which(executable)
-        .with_context(|| format!("'{}' could not be found! Make sure to add '/path/to/ffmpeg/bin' to the PATH variable", executable.display()))?
-    )
+    which(executable)
+        .with_context(|| format!("'{}' could not be found! Make sure to add '/path/to/ffmpeg/bin' to the PATH variable", executable.display()))
+}
+
+/// Sets the ffmpeg and ffprobe executables. Must be called before planning inputs that need media info
+/// (see [`crate::Planner::needs_media_info`]) and before running or probing ffmpeg jobs
+pub fn init_ffmpeg_paths(ffmpeg_path: &Path, ffprobe_path: &Path) -> Result<(), anyhow::Error> {
+    FFMPEG_PATH.get_or_init(|| Box::leak(ffmpeg_path.to_path_buf().into_boxed_path()));
+    FFPROBE_PATH.get_or_init(|| Box::leak(ffprobe_path.to_path_buf().into_boxed_path()));
+
+    assert_exists(FFMPEG_PATH.get().unwrap())?;
+    assert_exists(FFPROBE_PATH.get().unwrap())?;
+    Ok(())
 }
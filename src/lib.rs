@@ -0,0 +1,42 @@
+//! Batch conversion of files with ffmpeg.
+//!
+//! A [`Planner`] turns input files and directories into [`Job`]s using an [`ExtensionMap`]
+//! and an [`OutputPattern`], and a [`Runner`] executes them concurrently with progress bars.
+//!
+//! ```no_run
+//! use std::path::{Path, PathBuf};
+//! use lconvert::{init_ffmpeg_paths, ExtensionMap, OutputPattern, Planner, Runner};
+//!
+//! init_ffmpeg_paths(Path::new("ffmpeg"), Path::new("ffprobe"))?;
+//!
+//! let extension_map = ExtensionMap::from([("wav".to_owned(), vec!["mp3".to_owned()])]);
+//! let planner = Planner::new(OutputPattern::new(PathBuf::from("out/{{file}}"))?, extension_map);
+//! let plan = planner.plan(vec![PathBuf::from("music")])?;
+//!
+//! for completed in Runner::new(4).run(plan.jobs)?.completed {
+//!     if let Some(err) = completed.get_error() {
+//!         eprintln!("{}: {err}", completed.options.input_file.display());
+//!     }
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod config;
+pub mod converter;
+pub mod ffmpeg;
+pub mod media;
+pub mod parser;
+pub mod planner;
+pub mod progress;
+pub mod report;
+pub mod runner;
+pub mod sync;
+pub mod template;
+pub mod watch;
+
+pub use ffmpeg::{init_ffmpeg_paths, FFmpegError, FFmpegOptions, FFmpegProcessCompleted, FFmpegStatus};
+pub use media::{MediaInfo, StreamInfo, StreamKind};
+pub use parser::{ExtensionMap, OptionsMap, OutputPattern, PlaceholderSource};
+pub use planner::{Job, Plan, Planner, SkipReason, Skipped};
+pub use report::RunReport;
+pub use runner::{RunOutcome, Runner};
@@ -1,255 +1,375 @@
-mod progress;
-mod ffmpeg;
-mod parser;
-
-use std::{fs::{create_dir_all, read_dir, DirEntry}, io::{BufRead, BufReader}, path::PathBuf, time::Instant, process::Child};
-use anyhow::Context;
-use clap::Parser;
-use progress::{FFmpegProgress, OverallProgress};
-use ffmpeg::{assert_exists, FFmpegOptions, FFmpegProcessCompleted, FFmpegProcessStarted, FFMPEG_PATH, FFPROBE_PATH};
-use parser::{Arguments, ExtensionMap, get_longest_common_path, OutputPattern};
-
-struct FFmpegProcessWithProgress<'a> {
-    process: FFmpegProcessStarted,
-    progress: FFmpegProgress<'a>,
-}
-
-impl FFmpegProcessWithProgress<'_> {
-    pub fn finish(self) -> FFmpegProcessCompleted {
-        self.progress.finish();
-        self.process.finish()
+use std::{ffi::OsStr, io::{IsTerminal, Write}, path::{Path, PathBuf}, process::ExitCode, sync::atomic::Ordering, time::Instant};
+use lconvert::{init_ffmpeg_paths, FFmpegProcessCompleted, OutputPattern, Plan, Planner, RunOutcome, RunReport, Runner};
+use lconvert::config::{parse_arguments, Config};
+use lconvert::ffmpeg::{FFmpegStatus, FFmpegWarning};
+use lconvert::parser::{Arguments, Command, ConvertArguments, SyncArguments, get_longest_common_path};
+use lconvert::runner::{kill_running, probe_jobs};
+use lconvert::sync::{sync_planner, SyncPlan};
+use lconvert::watch::DirWatcher;
+
+const EXIT_PLANNING_ERROR: u8 = 1;
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+const EXIT_TOTAL_FAILURE: u8 = 4;
+const EXIT_INTERRUPTED: u8 = 130;
+
+fn quote_arg(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "'\"$`\\;&|<>()[]{}*?!#~".contains(c)) {
+        return arg.into_owned();
     }
+    format!("'{}'", arg.replace('\'', "'\\''"))
 }
 
-fn get_ffmpeg_options(
-    input_files: Vec<PathBuf>,
-    output_pattern: &OutputPattern,
-    extension_map: &ExtensionMap,
-    ffmpeg_str_options: &Vec<String>,
-    case_sensitive: bool,
-    allow_override: bool,
-    disable_pattern_append: bool,
-    tree: Option<PathBuf>
-) -> Result<Vec<FFmpegOptions>, anyhow::Error> {
-    let mut ffmpeg_options: Vec<FFmpegOptions> = Vec::new();
-
-    for input_file in input_files {
-        if input_file.is_dir() {
-            ffmpeg_options.extend(get_ffmpeg_options(
-                read_dir(&input_file)
-                    .with_context(|| format!("Cound not read directory: '{}'", input_file.display()))?
-                    .collect::<Result<Vec<DirEntry>, _>>()
-                    .with_context(|| format!("Error while reading directory: '{}'", input_file.display()))?
-                    .into_iter()
-                    .map(|x| input_file.join(x.path()))
-                    .collect(),
-                output_pattern,
-                extension_map,
-                ffmpeg_str_options,
-                case_sensitive,
-                allow_override,
-                disable_pattern_append,
-                if let Some(t) = &tree { 
-                    Some(t.join(input_file.file_name().with_context(|| format!("could not read file_name: {}", input_file.display()))?)) 
-                } else { 
-                    Some(input_file.file_name().with_context(|| format!("Could not read file_name: '{}'", input_file.display()))?.into()) 
-                }
-            )?);
-            continue;
+fn print_plan(plan: &Plan) {
+    let rows: Vec<[String; 5]> = plan.jobs.iter().map(|job| [
+        job.input_file.display().to_string(),
+        job.output_file.display().to_string(),
+        job.rule.clone(),
+        job.duration.map(|x| format!("{x:.1}s")).unwrap_or("-".to_owned()),
+        {
+            let (program, args) = job.get_command();
+            std::iter::once(program.as_os_str())
+                .chain(args.iter().map(|x| x.as_os_str()))
+                .map(quote_arg)
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+    ]).collect();
+
+    let header = ["INPUT", "OUTPUT", "RULE", "DURATION", "COMMAND"].map(String::from);
+    let mut widths = header.clone().map(|x| x.chars().count());
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
         }
+    }
 
-        let mut input_extension = input_file
-            .extension()
-            .with_context(|| format!("File has no extension: '{}'", input_file.display()))?
-            .to_str()
-            .with_context(|| format!("File has non utf-8 fucked up extension: '{}'", input_file.display()))?;
-
-        if !case_sensitive { for key in extension_map.keys() { 
-            if key.to_lowercase().eq(&input_extension.to_lowercase()) {
-                input_extension = key;
-                break; 
-            }
-        }}
-
-        if !extension_map.contains_key(input_extension) {
-            if extension_map.contains_key("*") {
-                input_extension = "*";
-            } else {
-                continue;
-            }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {}",
+            row[0], row[1], row[2], row[3], row[4],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3],
+        );
+    }
+
+    if !plan.skipped.is_empty() {
+        println!("\nSkipped:");
+        for skipped in plan.skipped.iter() {
+            println!("  '{}' ({})", skipped.input_file.display(), skipped.reason);
         }
+    }
 
-        let output_file = output_pattern.fill_blanks(
-            &input_file, 
-            extension_map, input_extension, 
-            &tree,
-            &ffmpeg_options,
-            allow_override,
-            disable_pattern_append,
-        )?;
-
-        ffmpeg_options.push(FFmpegOptions::new(
-            input_file, 
-            output_file, 
-            allow_override, 
-            ffmpeg_str_options.clone()
-        ))
-    }
-    return Ok(ffmpeg_options);
+    println!("\n{} planned, {} skipped", plan.jobs.len(), plan.skipped.len());
 }
 
-fn create_hierarchy(ffmpeg_options: &Vec<FFmpegOptions>) -> Result<(), anyhow::Error>{
-    for ffmpeg_option in ffmpeg_options{
-        create_dir_all(
-            ffmpeg_option.output_file.parent().with_context(|| format!("could not get parent of file: '{}'", ffmpeg_option.output_file.display()))?
-        ).with_context(|| format!("could not create directory hierarchy: '{}'", ffmpeg_option.output_file.parent().unwrap().display()))?;
+fn print_errors(completed_processes: &[FFmpegProcessCompleted]) {
+    for completed_process in completed_processes.iter() {
+        match completed_process.get_status() {
+            FFmpegStatus::Failure(err) => {
+                eprintln!("┌ Error while trying to process input file: '{}'", completed_process.options.input_file.display());
+                eprintln!("{err}");
+                eprintln!();
+            },
+            FFmpegStatus::Warning(warning) => {
+                eprintln!("┌ Warning while processing input file: '{}'", completed_process.options.input_file.display());
+                eprintln!("{}", FFmpegWarning(completed_process.options.converter.name(), &warning));
+                eprintln!();
+            },
+            FFmpegStatus::Success => {},
+        }
+    }
+
+    let n_warned = completed_processes.iter().filter(|x| x.get_warning().is_some()).count();
+    if n_warned > 0 {
+        eprintln!("{}/{} files finished with warnings", n_warned, completed_processes.len());
+    }
+
+    if completed_processes.iter().any(|x| x.get_error().is_some()) {
+        eprintln!(
+            "{}/{} files finished with errors!",
+            completed_processes.iter().filter(|x| x.get_error().is_some()).count(),
+            completed_processes.len(),
+        );
     }
-    Ok(())
 }
 
-fn update_progress(child: &mut Child, progress: &mut FFmpegProgress) {
-    if !progress.has_duration {
-        progress.update(None);
-        return;
+fn get_exit_code(outcome: &RunOutcome) -> ExitCode {
+    let n_failed = outcome.n_failed() + outcome.cancelled.len() + outcome.not_started.len();
+
+    if n_failed == 0 {
+        ExitCode::SUCCESS
+    } else if outcome.n_succeeded() == 0 {
+        ExitCode::from(EXIT_TOTAL_FAILURE)
+    } else {
+        ExitCode::from(EXIT_PARTIAL_FAILURE)
     }
+}
 
-    let output = child.stdout.as_mut().unwrap();
-    let reader = BufReader::new(output);
+fn restore_terminal() {
+    if cfg!(target_os = "linux") && std::io::stdin().is_terminal() {
+        // FIXME: For some reason on linux after the prgram is done (or interrupted), character echo
+        // is sometimes disabled. This fixes it but will need to find why that happens
+        let _ = std::process::Command::new("stty").arg("echo").status();
+    }
+}
 
-    for result in reader.lines() { 
-        if let Ok(line) = result {
-            if line.contains("out_time_ms") {
-                progress.update(Some(&line));
-                break;
-            }
+fn main() -> ExitCode {
+    let exit_code = match run(parse_arguments()) {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(EXIT_PLANNING_ERROR)
         }
     };
+
+    restore_terminal();
+    exit_code
 }
 
-fn update_process_with_progress<'a>(index: usize, process_with_progress: &'a mut FFmpegProcessWithProgress) -> Option<usize> {
-    match &mut process_with_progress.process.child {
-        Ok(child) => { 
-            match child.try_wait() { // Child exists
-                Ok(None) => { // Child is working
-                    update_progress(child, &mut process_with_progress.progress);
-                    return None;
-                },
-                _ => { // Child finished or error attemting to acces
-                    return Some(index);
-                }
-        }},
-        Err(_) => { // Child did not start
-            return Some(index);
-        },
+fn run(args: Arguments) -> anyhow::Result<ExitCode> {
+    match args.command {
+        Some(Command::Sync(sync_args)) => run_sync(*sync_args),
+        Some(Command::Presets) => run_presets(),
+        None => run_convert(args),
     }
 }
 
-fn update_processes_until_one_finishes<'a>(processes: &'a mut Vec<FFmpegProcessWithProgress>) -> FFmpegProcessCompleted {
-    loop { 
-        for (i, process_with_progress) in processes.iter_mut().enumerate() {
-            if let Some(finished_process_index) = update_process_with_progress(i, process_with_progress) {
-                return processes.remove(finished_process_index).finish();
-            };
+fn run_presets() -> anyhow::Result<ExitCode> {
+    let config = Config::load()?;
+    let mut names: Vec<&String> = config.presets.keys().collect();
+    names.sort_unstable();
+
+    for (i, name) in names.into_iter().enumerate() {
+        let preset = &config.presets[name];
+        if i > 0 {
+            println!();
         }
+        match &preset.path {
+            Some(path) => println!("{name} ({})", path.display()),
+            None => println!("{name} (built-in)"),
+        }
+        if let Some(description) = &preset.description {
+            println!("  {description}");
+        }
+        println!("  lconvert {}", preset.to_args().iter().map(|x| quote_arg(OsStr::new(x))).collect::<Vec<_>>().join(" "));
     }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn run_ffmpeg_concurrent(mut ffmpeg_options: Vec<FFmpegOptions>, n_subprocesses: u32) -> Vec<FFmpegProcessCompleted> {
-    let overall_progress = OverallProgress::new(
-        ffmpeg_options.iter().map(|x| x.duration.unwrap_or(1.0).floor() as u64).sum(),
-        ffmpeg_options.len() as u64
-    );
+fn run_convert(args: Arguments) -> anyhow::Result<ExitCode> {
+    let convert = args.convert.as_ref().expect("Required when no subcommand is given");
 
-    let mut started_processes: Vec<FFmpegProcessWithProgress> = Vec::new();
-    let mut completed_processes: Vec<FFmpegProcessCompleted> = Vec::new();
+    let start_time = Instant::now();
 
-    while let Some(ffmpeg_options) = ffmpeg_options.pop() {
-        // if more processes than limit, wait until one finishes
-        if started_processes.len() as u32 >= n_subprocesses { 
-            let completed_process = update_processes_until_one_finishes(&mut started_processes);
-            overall_progress.update_completed(&completed_process.get_error());
-            completed_processes.push(completed_process);
-        }
+    let input_files: Vec<PathBuf> = args.get_glob_expanded_input_files();
+
+    let output_pattern = if args.watch {
+        // Files converted later must not get a new unique output directory
+        OutputPattern::new(args.output.clone())?.resolve_unique_dirs()?
+    } else {
+        OutputPattern::new(args.output.clone())?
+    };
+
+    let planner = Planner {
+        ffmpeg_str_options: convert.ffmpeg_str_options.clone(),
+        ffmpeg_input_options: convert.get_input_options(),
+        command_template: convert.get_command_template(),
+        rule_command_templates: convert.get_rule_command_templates()?,
+        converters: convert.get_converters()?,
+        extension_options: convert.get_extension_options(),
+        rule_options: convert.extension_map.options.clone(),
+        case_sensitive: convert.case_sensitive,
+        allow_override: args.allow_override,
+        disable_pattern_append: args.disable_pattern_append,
+        tmp_dir: convert.tmp_dir.clone(),
+        skip_existing: args.skip_existing,
+        update: args.update,
+        n_probes: convert.get_n_probes(),
+        ..Planner::new(output_pattern, convert.extension_map.extension_map.clone())
+    };
 
-        started_processes.push(FFmpegProcessWithProgress {
-            progress: FFmpegProgress::new(&overall_progress, &ffmpeg_options),
-            process: ffmpeg_options.start(),
-        });
+    // ffmpeg and ffprobe are only looked for when something runs them, e.g. not for 'svg=png@magick'
+    let init_ffmpeg = || init_ffmpeg_paths(&convert.ffmpeg_path, &convert.ffprobe_path);
+    if planner.needs_media_info() {
+        init_ffmpeg()?;
+    }
+    let mut plan = planner.plan(input_files.clone())?;
+    if plan.uses_ffmpeg() || (args.watch && planner.uses_ffmpeg()) {
+        init_ffmpeg()?;
     }
 
-    while !started_processes.is_empty() {
-        let completed_process = update_processes_until_one_finishes(&mut started_processes);
-        overall_progress.update_completed(&completed_process.get_error());
-        completed_processes.push(completed_process);
+    if convert.dry_run {
+        probe_jobs(&mut plan.jobs, convert.get_n_probes());
+        print_plan(&plan);
+        return Ok(ExitCode::SUCCESS);
     }
 
-    overall_progress.finish();
-    completed_processes
+    let watcher = if args.watch {
+        let roots: Vec<PathBuf> = input_files.into_iter().filter(|x| x.is_dir()).collect();
+        if roots.is_empty() {
+            anyhow::bail!("'--watch' needs at least one input directory");
+        }
+        Some(DirWatcher {
+            settle_time: args.settle_time,
+            planned: Plan { jobs: plan.jobs.clone(), n_inputs: plan.n_inputs, ..Plan::default() },
+            ..DirWatcher::new(planner, roots)
+        })
+    } else {
+        None
+    };
+
+    run_jobs(plan, convert, watcher, start_time)
 }
 
-fn print_errors(completed_processes: &Vec<FFmpegProcessCompleted>) {
-    for completed_process in completed_processes.iter() { 
-        if let Some(err) = completed_process.get_error() { 
-            eprintln!("┌ Error while trying to process input file: '{}'", completed_process.options.input_file.display());
-            eprintln!("{err}");
-            eprintln!();
-        }
+fn print_sync_summary(sync_plan: &SyncPlan, mirror: &Path) {
+    let relative = |path: &Path| path.strip_prefix(mirror).unwrap_or(path).display().to_string();
+
+    for job in sync_plan.added() {
+        println!("+ {}", relative(&job.output_file));
+    }
+    for job in sync_plan.changed() {
+        println!("~ {}", relative(&job.output_file));
+    }
+    for orphan in sync_plan.orphans.iter() {
+        println!("- {}", relative(orphan));
     }
 
-    if completed_processes.iter().any(|x| x.get_error().is_some()) {
-        eprintln!(
-            "{}/{} files finished with errors!", 
-            completed_processes.iter().filter(|x| x.get_error().is_some()).count(),
-            completed_processes.len(), 
-        );
+    println!(
+        "\n{} new, {} changed, {} orphaned, {} unchanged\n",
+        sync_plan.added().count(),
+        sync_plan.changed().count(),
+        sync_plan.orphans.len(),
+        sync_plan.unchanged().count(),
+    );
+}
+
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
     }
+
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
 }
 
-fn init_ffmpeg_paths(args: &Arguments) -> Result<(), anyhow::Error> {
-    FFMPEG_PATH.get_or_init(|| Box::leak(args.ffmpeg_path.clone().into_boxed_path()));
-    FFPROBE_PATH.get_or_init(|| Box::leak(args.ffprobe_path.clone().into_boxed_path()));
+fn run_sync(args: SyncArguments) -> anyhow::Result<ExitCode> {
+    let convert = &args.convert;
+
+    let start_time = Instant::now();
+
+    if !args.source.is_dir() {
+        anyhow::bail!("Source is not a directory: '{}'", args.source.display());
+    }
+
+    let planner = Planner {
+        ffmpeg_str_options: convert.ffmpeg_str_options.clone(),
+        ffmpeg_input_options: convert.get_input_options(),
+        command_template: convert.get_command_template(),
+        rule_command_templates: convert.get_rule_command_templates()?,
+        converters: convert.get_converters()?,
+        extension_options: convert.get_extension_options(),
+        rule_options: convert.extension_map.options.clone(),
+        case_sensitive: convert.case_sensitive,
+        tmp_dir: convert.tmp_dir.clone(),
+        n_probes: convert.get_n_probes(),
+        ..sync_planner(&args.output, convert.extension_map.extension_map.clone())
+    };
+
+    let init_ffmpeg = || init_ffmpeg_paths(&convert.ffmpeg_path, &convert.ffprobe_path);
+    if planner.needs_media_info() {
+        init_ffmpeg()?;
+    }
+    let mut sync_plan = SyncPlan::new(&planner, &args.source, &args.output)?;
+    if sync_plan.plan.uses_ffmpeg() {
+        init_ffmpeg()?;
+    }
+
+    print_sync_summary(&sync_plan, &args.output);
+
+    if convert.dry_run {
+        probe_jobs(&mut sync_plan.plan.jobs, convert.get_n_probes());
+        print_plan(&sync_plan.plan);
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    assert_exists(FFMPEG_PATH.get().unwrap())?;
-    assert_exists(FFPROBE_PATH.get().unwrap())?;
-    Ok(())
+    if !sync_plan.orphans.is_empty() {
+        if args.delete || confirm(&format!("Remove {} orphaned files from '{}'?", sync_plan.orphans.len(), args.output.display()))? {
+            sync_plan.remove_orphans(&args.output)?;
+            println!("Removed {} orphaned files\n", sync_plan.orphans.len());
+        } else {
+            println!("Kept {} orphaned files, use '--delete' to remove them\n", sync_plan.orphans.len());
+        }
+    }
+
+    run_jobs(sync_plan.plan, convert, None, start_time)
 }
 
-fn main() -> anyhow::Result<()> {
-    let args: Arguments = Arguments::parse();
+/// Runs planned jobs (and watched ones, until interrupted) and prints their summary, shared by every subcommand
+fn run_jobs(plan: Plan, convert: &ConvertArguments, watcher: Option<DirWatcher>, start_time: Instant) -> anyhow::Result<ExitCode> {
+    let Plan { jobs, skipped, .. } = plan;
 
-    init_ffmpeg_paths(&args)?;
+    println!("Total files      :  {}", jobs.len());
+    let n_converted = skipped.iter().filter(|x| x.output_file.is_some()).count();
+    if n_converted > 0 {
+        println!("Skipped files    :  {} (already converted)", n_converted);
+    }
+    println!("Output directory : '{}'", get_longest_common_path(jobs.iter().map(|x| x.output_file.as_path()).collect()).unwrap_or_default().display());
 
-    let start_time = Instant::now();
+    let runner = Runner {
+        n_probes: convert.get_n_probes(),
+        fail_fast: convert.fail_fast,
+        ..Runner::new(convert.n_subprocesses)
+    };
 
-    let input_files: Vec<PathBuf> = args.get_glob_expanded_input_files();
-    let output_pattern = OutputPattern::new(args.output);
-
-    let ffmpeg_options: Vec<FFmpegOptions> = get_ffmpeg_options(
-        input_files, 
-        &output_pattern,
-        &args.extension_map, 
-        &args.ffmpeg_str_options,
-        args.case_sensitive, 
-        args.allow_override, 
-        args.disable_pattern_append,
-        None
-    )?;
+    // First interrupt cancels the run gracefully, the second one exits immediately.
+    // Children are in their own process groups and do not get the signal, they are killed here
+    let cancel = runner.cancel.clone();
+    let running = runner.running.clone();
+    ctrlc::set_handler(move || {
+        if cancel.swap(true, Ordering::SeqCst) {
+            kill_running(&running);
+            restore_terminal();
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+    })?;
 
-    create_hierarchy(&ffmpeg_options)?;
+    let outcome = if let Some(watcher) = watcher {
+        println!("Watching {} directories for new files, press Ctrl-C to stop", watcher.roots.len());
+        runner.run_with_incoming(jobs, watcher.spawn(runner.cancel.clone(), runner.progress.clone())?)?
+    } else {
+        runner.run(jobs)?
+    };
+    let interrupted = runner.cancel.load(Ordering::SeqCst);
 
-    println!("Total files      :  {}", ffmpeg_options.len());
-    println!("Output directory : '{}'", get_longest_common_path(ffmpeg_options.iter().map(|x| x.output_file.as_path()).collect()).unwrap_or_default().display());
+    if interrupted {
+        println!("\nInterrupted after {:.1?}!\n", Instant::now().duration_since(start_time));
+    } else {
+        println!("\nDone in {:.1?}!\n", Instant::now().duration_since(start_time));
+    }
 
-    let completed_processes: Vec<FFmpegProcessCompleted> = run_ffmpeg_concurrent(ffmpeg_options, args.n_subprocesses);
+    print_errors(&outcome.completed);
 
-    println!("\nDone in {:.1?}!\n", Instant::now().duration_since(start_time));
+    if interrupted {
+        eprintln!(
+            "{} files converted, {} cancelled (partial outputs removed), {} not started",
+            outcome.n_succeeded(),
+            outcome.cancelled.len(),
+            outcome.not_started.len(),
+        );
+    } else if !outcome.not_started.is_empty() {
+        eprintln!("{} files were not converted because of '--fail-fast'", outcome.not_started.len());
+    }
 
-    print_errors(&completed_processes);
+    if let Some(report) = &convert.report {
+        RunReport::new(&outcome, &skipped, start_time.elapsed()).write(report)?;
+    }
 
-    if cfg!(target_os = "linux") {
-        // FIXME: For some reason on linux after the prgram is done, character echo is disabled
-        // This fixes it but will need to find why that happens
-        std::process::Command::new("stty").arg("echo").spawn()?.wait()?;
+    if interrupted {
+        return Ok(ExitCode::from(EXIT_INTERRUPTED));
     }
-    Ok(())
+    Ok(get_exit_code(&outcome))
 }
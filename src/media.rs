@@ -0,0 +1,214 @@
+use std::{collections::HashMap, path::Path, process::{Command, Stdio}, sync::Mutex, thread};
+use anyhow::Context;
+use indicatif::MultiProgress;
+use serde::{Deserialize, Serialize};
+use crate::ffmpeg::FFPROBE_PATH;
+use crate::progress::ProbeProgress;
+
+/// What ffprobe knows about an input file
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MediaInfo {
+    /// Container format as named by ffprobe, e.g. 'mp3' or 'mov,mp4,m4a,3gp,3g2,mj2'
+    pub container: Option<String>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Overall bit rate in bits per second
+    pub bit_rate: Option<u64>,
+    /// Container tags, keys are lowercase
+    pub tags: HashMap<String, String>,
+    pub streams: Vec<StreamInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamInfo {
+    pub index: usize,
+    pub kind: StreamKind,
+    pub codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Average frames per second
+    pub frame_rate: Option<f64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// Bit rate in bits per second
+    pub bit_rate: Option<u64>,
+    /// Number of frames, if the container stores it
+    pub frame_count: Option<u64>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Stream tags, keys are lowercase
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    #[serde(other)]
+    Other,
+}
+
+impl MediaInfo {
+    /// Runs `ffprobe -show_format -show_streams -of json` on the file
+    pub fn probe(path: &Path) -> Result<Self, anyhow::Error> {
+        let output = Command::new(FFPROBE_PATH.get().expect("Initialized with init_ffmpeg_paths"))
+            .args(["-v", "error", "-show_format", "-show_streams", "-of", "json"])
+            .arg(path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .with_context(|| format!("Could not run ffprobe on: '{}'", path.display()))?;
+
+        if !output.status.success() {
+            anyhow::bail!("ffprobe failed on '{}': {}", path.display(), String::from_utf8_lossy(&output.stderr).trim_end());
+        }
+
+        Self::from_json(&String::from_utf8_lossy(&output.stdout))
+            .with_context(|| format!("Could not parse ffprobe output for: '{}'", path.display()))
+    }
+
+    /// Parses the output of `ffprobe -show_format -show_streams -of json`
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(serde_json::from_str::<FFprobeOutput>(json)?.into())
+    }
+
+    pub fn first_stream(&self, kind: StreamKind) -> Option<&StreamInfo> {
+        self.streams.iter().find(|x| x.kind == kind)
+    }
+
+    pub fn video(&self) -> Option<&StreamInfo> {
+        self.first_stream(StreamKind::Video)
+    }
+
+    pub fn audio(&self) -> Option<&StreamInfo> {
+        self.first_stream(StreamKind::Audio)
+    }
+
+    /// Container tag, or the tag of the first stream that has it (e.g. Vorbis comments live on the audio stream)
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.tags.get(&name)
+            .or_else(|| self.streams.iter().find_map(|x| x.tags.get(&name)))
+            .map(|x| x.as_str())
+    }
+
+    /// Number of video frames, estimated from duration and frame rate if the container does not store it
+    pub fn frame_count(&self) -> Option<u64> {
+        let video = self.video()?;
+        video.frame_count.or_else(|| {
+            let duration = video.duration.or(self.duration)?;
+            Some((duration * video.frame_rate?).round() as u64)
+        })
+    }
+}
+
+/// Probes files with up to `n_probes` concurrent ffprobe processes, `None` for files ffprobe failed on
+pub fn probe_files(paths: Vec<&Path>, n_probes: u32) -> Vec<Option<MediaInfo>> {
+    let progress = ProbeProgress::new(&MultiProgress::new(), paths.len() as u64);
+    let remaining = Mutex::new(paths.iter().enumerate());
+    let next_path = || remaining.lock().unwrap().next();
+    let probed = Mutex::new(vec![None; paths.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..n_probes {
+            scope.spawn(|| {
+                while let Some((i, path)) = next_path() {
+                    let media_info = MediaInfo::probe(path).ok();
+                    probed.lock().unwrap()[i] = media_info;
+                    progress.update_probed();
+                }
+            });
+        }
+    });
+    progress.finish();
+    probed.into_inner().unwrap()
+}
+
+// Raw ffprobe output, numbers are mostly printed as strings
+
+#[derive(Deserialize)]
+struct FFprobeOutput {
+    format: Option<FFprobeFormat>,
+    #[serde(default)]
+    streams: Vec<FFprobeStream>,
+}
+
+#[derive(Deserialize)]
+struct FFprobeFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct FFprobeStream {
+    index: usize,
+    codec_type: Option<StreamKind>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    bit_rate: Option<String>,
+    nb_frames: Option<String>,
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+impl From<FFprobeOutput> for MediaInfo {
+    fn from(output: FFprobeOutput) -> Self {
+        let streams: Vec<StreamInfo> = output.streams.into_iter().map(StreamInfo::from).collect();
+        let format = output.format;
+
+        Self {
+            container: format.as_ref().and_then(|x| x.format_name.clone()),
+            duration: format.as_ref().and_then(|x| parse(&x.duration))
+                .or_else(|| streams.iter().filter_map(|x| x.duration).reduce(f64::max)),
+            bit_rate: format.as_ref().and_then(|x| parse(&x.bit_rate)),
+            tags: format.map(|x| lowercase_keys(x.tags)).unwrap_or_default(),
+            streams,
+        }
+    }
+}
+
+impl From<FFprobeStream> for StreamInfo {
+    fn from(stream: FFprobeStream) -> Self {
+        Self {
+            index: stream.index,
+            kind: stream.codec_type.unwrap_or(StreamKind::Other),
+            codec: stream.codec_name,
+            width: stream.width,
+            height: stream.height,
+            frame_rate: stream.avg_frame_rate.as_deref().and_then(parse_ratio),
+            sample_rate: parse(&stream.sample_rate),
+            channels: stream.channels,
+            bit_rate: parse(&stream.bit_rate),
+            frame_count: parse(&stream.nb_frames),
+            duration: parse(&stream.duration),
+            tags: lowercase_keys(stream.tags),
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &Option<String>) -> Option<T> {
+    value.as_deref()?.parse().ok()
+}
+
+/// '30000/1001' -> 29.97, '0/0' -> None
+fn parse_ratio(ratio: &str) -> Option<f64> {
+    let (numerator, denominator) = ratio.split_once('/')?;
+    let (numerator, denominator) = (numerator.parse::<f64>().ok()?, denominator.parse::<f64>().ok()?);
+    (denominator != 0.0 && numerator != 0.0).then(|| numerator / denominator)
+}
+
+fn lowercase_keys(tags: HashMap<String, String>) -> HashMap<String, String> {
+    tags.into_iter().map(|(key, value)| (key.to_lowercase(), value)).collect()
+}
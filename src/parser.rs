@@ -1,24 +1,42 @@
-use std::{collections::HashMap, path::{absolute, PathBuf, Path}};
-use clap::{arg, builder::ValueParser, command, error::Result, value_parser, Parser, ValueHint};
+use std::{cell::OnceCell, collections::HashMap, fmt::Write, fs::{metadata, File}, path::{absolute, PathBuf, Path}, sync::Arc, time::Duration};
+use chrono::{DateTime, Local};
+use chrono::format::{Item, StrftimeItems};
+use clap::{builder::ValueParser, error::Result, value_parser, Args, Parser, Subcommand, ValueHint};
 use glob::{glob, GlobError};
 use anyhow::Context;
-use crate::FFmpegOptions;
+use crate::converter::{converter_by_name, Converter, CONVERTER_NAMES};
+use crate::ffmpeg::FFmpegOptions;
+use crate::media::MediaInfo;
+use crate::template::{nearest, Placeholder, Template};
 
 // let r = r#"^((\w+)|(\w+=\w+)(,\w+=\w+)*)$"#;
-const EXTENSION_MAP_REGEX: &str = r#"^((\w+)(,\w+=\w+)*|(\w+=\w+)(,\w+=\w+)*(,\w+)?(,\w+=\w+)*)$"#;
+// A single output of an extension map rule with its @CONVERTER, without its [OPTIONS]
+const EXTENSION_TARGET_REGEX: &str = r#"^\w+(@\w+)?$"#;
 const DEFAULT_PATTERN: &str = "lconvert_output{{unique-suffix}}/{{tree}}/{{file}}";
 
-pub type ExtensionMap = HashMap<String, String>;
+/// Output extensions by input extension ('*' for the wildcard), every output gets its own job
+pub type ExtensionMap = HashMap<String, Vec<String>>;
+
+/// ffmpeg options by extension, e.g. from '--opts mp3:-b:a 192k'
+pub type OptionsMap = HashMap<String, Vec<String>>;
+
+/// Extension map with the converters and options given to its rules, e.g. 'wav=mp3[-b:a 192k]+flac,docx=pdf@soffice'
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionRules {
+    pub extension_map: ExtensionMap,
+    /// Options by rule ('IN_EXT=OUT_EXT', IN_EXT is '*' for the wildcard)
+    pub options: OptionsMap,
+    /// Converter names by rule, e.g. 'soffice' for 'docx=pdf'. ffmpeg if missing
+    pub converters: HashMap<String, String>,
+}
 
 pub fn parser_input_files() -> ValueParser {
     ValueParser::from(move |s: &str| -> std::result::Result<PathBuf, String> { 
         // TODO: Currently expanding glob two times: one for validation and another for actual values
-        let path: PathBuf;
-
-        match absolute(s) {
-            Ok(p) => path = p,
+        let path: PathBuf = match absolute(s) {
+            Ok(p) => p,
             Err(err) => return Err(err.to_string()),
-        }
+        };
 
         if path.exists() {
             return Ok(path);
@@ -36,9 +54,9 @@ pub fn parser_input_files() -> ValueParser {
                 }
 
                 if is_empty {
-                    return Err(format!("File Not Found: '{s}'"))
+                    Err(format!("File Not Found: '{s}'"))
                 } else {
-                    return Ok(path);
+                    Ok(path)
                 }
             },
             Err(err) => Err(err.to_string()),
@@ -46,23 +64,213 @@ pub fn parser_input_files() -> ValueParser {
     })
 }
 
+pub fn parser_output_pattern() -> ValueParser {
+    ValueParser::from(move |s: &str| -> std::result::Result<PathBuf, String> {
+        OutputPattern::new(PathBuf::from(s))
+            .map(|_| PathBuf::from(s))
+            .map_err(|err| format!("{err:#}"))
+    })
+}
+
+pub fn parser_ffmpeg_option() -> ValueParser {
+    ValueParser::from(move |s: &str| -> std::result::Result<String, String> {
+        OutputPattern::validate_text(s)
+            .map(|_| s.to_owned())
+            .map_err(|err| format!("{err:#}"))
+    })
+}
+
 pub fn parser_extension_map() -> ValueParser {
-    ValueParser::from(move |s: &str| -> std::result::Result<ExtensionMap, String> {
-        let reg = regex::Regex::new(EXTENSION_MAP_REGEX).unwrap();
-
-        match reg.is_match(s) {
-            true => Ok(s.split(',').map(|x| {
-                let (key, value) = x.split_once('=').unwrap_or(("*", x));
-                (key.to_owned(), value.to_owned())
-            }).collect()),
-            false => Err(format!("Does not match expression: {EXTENSION_MAP_REGEX}")),
+    ValueParser::from(move |s: &str| -> std::result::Result<ExtensionRules, String> {
+        let reg = regex::Regex::new(EXTENSION_TARGET_REGEX).unwrap();
+        let invalid = |rule: &str| format!("Invalid rule '{rule}', expected IN_EXT=OUT_EXT or OUT_EXT, optionally followed by @CONVERTER \
+            and [OPTIONS]. Several outputs are joined with '+': IN_EXT=OUT_EXT+OUT_EXT");
+        let mut rules = ExtensionRules::default();
+
+        for rule in split_outside_brackets(s, ',')? {
+            let head_end = rule.find('[').unwrap_or(rule.len());
+            let (key, targets) = match rule[..head_end].split_once('=') {
+                Some((key, _)) => (key, &rule[key.len() + 1..]),
+                None => ("*", rule),
+            };
+            if key != "*" && (key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_')) {
+                return Err(invalid(rule));
+            }
+            if key == "*" && rules.extension_map.contains_key(key) {
+                return Err(format!("There may be only one wildcard rule: '{s}'"));
+            }
+
+            for target in split_outside_brackets(targets, '+')? {
+                let (target, options) = match target.split_once('[') {
+                    Some((target, options)) => match options.strip_suffix(']') {
+                        Some(options) => (target, split_options(options)?),
+                        None => return Err(format!("Options of rule '{rule}' must end the output they belong to: '{s}'")),
+                    },
+                    None => (target, Vec::new()),
+                };
+                if !reg.is_match(target) {
+                    return Err(invalid(rule));
+                }
+
+                let (value, converter) = match target.split_once('@') {
+                    Some((value, converter)) if CONVERTER_NAMES.contains(&converter) => (value, Some(converter)),
+                    Some((_, converter)) => match nearest(converter, &CONVERTER_NAMES) {
+                        Some(nearest) => return Err(format!("Unknown converter '{converter}', did you mean '{nearest}'?")),
+                        None => return Err(format!("Unknown converter '{converter}', expected one of: {}", CONVERTER_NAMES.join(", "))),
+                    },
+                    None => (target, None),
+                };
+                for option in options.iter() {
+                    OutputPattern::validate_text(option).map_err(|err| format!("{err:#}"))?;
+                }
+
+                let outputs = rules.extension_map.entry(key.to_owned()).or_default();
+                if outputs.iter().any(|x| x == value) {
+                    return Err(format!("Output extension '{value}' appears twice for '{key}': '{s}'"));
+                }
+                outputs.push(value.to_owned());
+
+                let key = format!("{key}={value}");
+                if !options.is_empty() {
+                    rules.options.insert(key.clone(), options);
+                }
+                if let Some(converter) = converter {
+                    rules.converters.insert(key, converter.to_owned());
+                }
+            }
         }
+
+        Ok(rules)
     })
 }
 
+pub fn parser_extension_options() -> ValueParser {
+    ValueParser::from(move |s: &str| -> std::result::Result<(String, Vec<String>), String> {
+        let Some((extension, options)) = s.split_once(':').filter(|(x, _)| !x.is_empty() && x.chars().all(|c| c.is_alphanumeric() || c == '_')) else {
+            return Err(format!("Expected OUT_EXT:OPTIONS, e.g. 'mp3:-b:a 192k', got: '{s}'"));
+        };
+        let options = split_options(options)?;
+        for option in options.iter() {
+            OutputPattern::validate_text(option).map_err(|err| format!("{err:#}"))?;
+        }
+        Ok((extension.to_owned(), options))
+    })
+}
+
+pub fn parser_input_options() -> ValueParser {
+    ValueParser::from(move |s: &str| -> std::result::Result<String, String> {
+        for option in split_options(s)? {
+            OutputPattern::validate_text(&option).map_err(|err| format!("{err:#}"))?;
+        }
+        Ok(s.to_owned())
+    })
+}
+
+pub fn parser_command_template() -> ValueParser {
+    ValueParser::from(move |s: &str| -> std::result::Result<(Option<String>, Vec<String>), String> {
+        let (key, mut args) = split_command(s)?;
+        // '/usr/bin/ffmpeg' or 'ffmpeg.exe' too, the configured ffmpeg is always used
+        if args.first().and_then(|x| Path::new(x).file_stem()).is_some_and(|x| x.eq_ignore_ascii_case("ffmpeg")) {
+            args.remove(0);
+        }
+        OutputPattern::validate_command(&args).map_err(|err| format!("{err:#}"))?;
+
+        Ok((key, args))
+    })
+}
+
+pub fn parser_converter_command() -> ValueParser {
+    ValueParser::from(move |s: &str| -> std::result::Result<(Option<String>, Vec<String>), String> {
+        let (key, args) = split_command(s)?;
+        match args.first() {
+            Some(program) if !program.contains("{{") => (),
+            _ => return Err(format!("Command must start with a program: '{s}'")),
+        }
+        OutputPattern::validate_command(&args[1..]).map_err(|err| format!("{err:#}"))?;
+
+        Ok((key, args))
+    })
+}
+
+/// Splits '[IN_EXT[=OUT_EXT]:]COMMAND' into the extension map key (or rule) and arguments
+fn split_command(s: &str) -> std::result::Result<(Option<String>, Vec<String>), String> {
+    let (key, command) = match s.split_once(':') {
+        Some((key, command)) if !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '=') => (Some(key.to_owned()), command),
+        _ => (None, s),
+    };
+    Ok((key, split_options(command)?))
+}
+
+/// Splits an extension map on `separator` (',' between rules, '+' between outputs) where it is not inside [OPTIONS]
+fn split_outside_brackets(s: &str, separator: char) -> std::result::Result<Vec<&str>, String> {
+    let mut rules = Vec::new();
+    let mut start = 0;
+    let mut in_options = false;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' if !in_options => in_options = true,
+            ']' if in_options => in_options = false,
+            '[' | ']' => return Err(format!("Unbalanced brackets in: '{s}'")),
+            _ if c == separator && !in_options => {
+                rules.push(&s[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    if in_options {
+        return Err(format!("Unbalanced brackets in: '{s}'"));
+    }
+    rules.push(&s[start..]);
+    Ok(rules)
+}
+
+/// Splits options on whitespace, like a shell would, quotes keep whitespace inside an option
+fn split_options(s: &str) -> std::result::Result<Vec<String>, String> {
+    let mut options = Vec::new();
+    let mut option: Option<String> = None;
+    let mut quote: Option<char> = None;
+
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => option.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                option.get_or_insert_with(String::new);
+            },
+            (None, c) if c.is_whitespace() => options.extend(option.take()),
+            (None, c) => option.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("Unclosed quote in options: '{s}'"));
+    }
+    options.extend(option);
+    Ok(options)
+}
+
 #[derive(Parser, Debug)]
-#[command(version, about = "Convert large amounts of files", long_about = None)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    about = "Convert large amounts of files",
+    long_about = None,
+    after_long_help =
+        "Exit codes:\n\
+         * 0 - All files converted\n\
+         * 1 - Error before any conversion started (planning, missing ffmpeg, ...)\n\
+         * 2 - Invalid arguments\n\
+         * 3 - Some files failed to convert\n\
+         * 4 - All files failed to convert\n\
+         * 130 - Interrupted (Ctrl-C), unfinished outputs were removed",
+)]
 pub struct Arguments {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Any file with an extension, directory, or a glob pattern
     #[arg(
         required = true,
@@ -71,29 +279,13 @@ pub struct Arguments {
     )]
     input_files: Vec<PathBuf>,
 
-    /// Maps input extension to output extension (see examples with '--help')
-    #[arg(
-        short = 'm', 
-        long, 
-        required = true,
-        long_help = 
-            "Maps input file extension to desired output file extension\n\n\
-             Examples:\
-             \n* 'jpeg=png' will convert any input file with .jpeg extension to .png\
-             \n* 'jpeg=png,mp4=avi' will convert .jpeg to .png and .mp4 to .avi\
-             \n* 'jpeg' is a wildcard and will try to convert all input files to .jpeg\
-             \n* 'mp3=ogg,jpeg,mp4=avi' there may be exactly one wildcard",
-        value_name = "IN_EXT=OUT_EXT",
-        value_parser = parser_extension_map(), 
-    )]
-    pub extension_map: ExtensionMap,
-
     /// Output pattern
     #[arg(
         short = 'o',
         long,
         value_name = "PATTERN",
         default_value = DEFAULT_PATTERN,
+        value_parser = parser_output_pattern(),
         value_hint = ValueHint::DirPath, 
         long_help = format!(
             "Output pattern\n\n\
@@ -107,8 +299,27 @@ pub struct Arguments {
              * {} - Parent of the input file\n\
              * {} - Input extension\n\
              * {} - Output extension\n\
-             * {} - A unique suffix (_<UNIQUE_NUMBER>). Replaced by empty string if directory or file is already unique\n\n\
+             * {} - A unique suffix (_<UNIQUE_NUMBER>). Replaced by empty string if directory or file is already unique\n\
+             * {} - Number of the input, counting from 1 in directory order. {{{{index:05}}}} pads it to 5 digits\n\
+             * {} - Date of the input ('creation_time' tag or modification time), e.g. {{{{date:%Y-%m-%d}}}}\n\
+             * {} - Time the run started, e.g. {{{{now:%Y%m%d}}}}\n\
+             * {} - First N hex characters of the blake3 hash of the input content ({{{{hash}}}} has {})\n\n\
+             Metadata placeholders (the inputs are probed with ffprobe before planning):\n\
+             * {} - Tag of the input, e.g. {{{{tag:artist}}}} or {{{{tag:album}}}}\n\
+             * {}, {} - Resolution of the first video stream\n\
+             * {} - Codec of the first video stream, or audio stream if there is no video\n\
+             * {} - Duration in seconds\n\
+             A number after a colon pads the value with zeros ({{{{tag:track:02}}}} turns '3/12' into '03'),\n\
+             and text after a question mark is used when the value is missing ({{{{tag:artist?Unknown Artist}}}}).\n\
+             Without it, missing values become '{}'.\n\n\
+             Filters transform a value, several can be chained: {{{{tag:title|slug|trunc:40}}}}\n\
+             * lower, upper - Change the case\n\
+             * slug - Lowercase, anything but letters and digits becomes '-' ('My Song (Live)' -> 'my-song-live')\n\
+             * replace:FROM,TO - Replace text, e.g. {{{{stem|replace: ,_}}}} replaces spaces with underscores\n\
+             * trunc:N - Keep the first N characters\n\
+             * default:TEXT - Use TEXT if the value is empty\n\n\
              Important:\n\
+             * Braces are written twice to be used literally: '{{{{{{{{' and '}}}}}}}}'.\n\
              * The last element of the pattern will always have an output extension.\
              \n  If it did not have an extension, it will be added, if it did, it will be changed.\n\
              * For convinience, any pattern with no placeholders will be appended with {} and {}.\
@@ -121,6 +332,17 @@ pub struct Arguments {
              OutputPattern::IN_EXT,
              OutputPattern::OUT_EXT,
              OutputPattern::UNIQUE_SUFFIX,
+             OutputPattern::INDEX,
+             OutputPattern::DATE,
+             OutputPattern::NOW,
+             OutputPattern::HASH,
+             OutputPattern::DEFAULT_HASH_LENGTH,
+             OutputPattern::TAG,
+             OutputPattern::WIDTH,
+             OutputPattern::HEIGHT,
+             OutputPattern::CODEC,
+             OutputPattern::DURATION,
+             OutputPattern::DEFAULT_FALLBACK,
              OutputPattern::TREE,
              OutputPattern::FILE,
              OutputPattern::TREE,
@@ -129,6 +351,219 @@ pub struct Arguments {
     )]
     pub output: PathBuf,
 
+    /// Disables appending {{tree}}/{{file}} to output patterns with no placeholders
+    #[arg(
+        short = 'd',
+        long,
+        overrides_with = "no_disable_pattern_append",
+    )]
+    pub disable_pattern_append: bool,
+
+    /// Undo '-d' of a preset
+    #[arg(long, overrides_with = "disable_pattern_append")]
+    pub no_disable_pattern_append: bool,
+
+    /// Allow ffmpeg to override files
+    #[arg(
+        short = 'y',
+        long,
+        overrides_with = "no_allow_override",
+    )]
+    pub allow_override: bool,
+
+    /// Undo '-y' of a preset
+    #[arg(long, overrides_with = "allow_override")]
+    pub no_allow_override: bool,
+
+    /// Skip files whose output already exists
+    #[arg(
+        long,
+        overrides_with = "no_skip_existing",
+        long_help =
+            "Skip files whose output already exists instead of failing them\n\n\
+             Use an output pattern without {{unique-suffix}}, otherwise every run gets new outputs.",
+    )]
+    pub skip_existing: bool,
+
+    /// Undo '--skip-existing' of a preset
+    #[arg(long, overrides_with = "skip_existing")]
+    pub no_skip_existing: bool,
+
+    /// Skip files whose output is newer than the input, reconvert the rest
+    #[arg(
+        long,
+        conflicts_with = "skip_existing",
+        overrides_with = "no_update",
+        long_help =
+            "Skip files whose output is newer than the input, reconvert (override) the outdated ones\n\n\
+             Use an output pattern without {{unique-suffix}}, otherwise every run gets new outputs.",
+    )]
+    pub update: bool,
+
+    /// Undo '--update' of a preset
+    #[arg(long, overrides_with = "update")]
+    pub no_update: bool,
+
+    /// Keep running and convert files that appear in the input directories
+    #[arg(
+        short = 'w',
+        long,
+        long_help =
+            "Keep running after the initial batch and convert files that are created or modified\n\
+             in the input directories (recursively), once they stop growing. Stop with Ctrl-C.",
+    )]
+    pub watch: bool,
+
+    /// Seconds a watched file must stay unchanged before it is converted
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "2",
+        requires = "watch",
+        value_parser = |s: &str| s.parse::<f64>().ok().filter(|x| x.is_finite() && *x >= 0.0).map(Duration::from_secs_f64).ok_or("Expected a non-negative number of seconds"),
+    )]
+    pub settle_time: Duration,
+
+    #[command(flatten)]
+    pub convert: Option<ConvertArguments>,
+}
+
+impl Arguments {
+    pub fn get_glob_expanded_input_files(&self) -> Vec<PathBuf> {
+        let mut results: Vec<PathBuf> = Vec::new();
+
+        for input_file in self.input_files.iter() {
+            if input_file.exists() {
+                results.push(input_file.clone());
+            } else {
+                results.extend(glob(input_file.to_str().unwrap())
+                    .expect("Validated this during parsing")
+                    .collect::<Result<Vec<PathBuf>, GlobError>>()
+                    .expect("Validated this during parsing")
+                );
+            }
+        }
+        results
+    }
+}
+
+/// Conversion options shared by every subcommand
+#[derive(Args, Debug)]
+pub struct ConvertArguments {
+    /// Maps input extension to output extension (see examples with '--help')
+    #[arg(
+        short = 'm', 
+        long, 
+        required = true,
+        long_help = 
+            "Maps input file extension to desired output file extension\n\n\
+             Examples:\
+             \n* 'jpeg=png' will convert any input file with .jpeg extension to .png\
+             \n* 'jpeg=png,mp4=avi' will convert .jpeg to .png and .mp4 to .avi\
+             \n* 'jpeg' is a wildcard and will try to convert all input files to .jpeg\
+             \n* 'mp3=ogg,jpeg,mp4=avi' there may be exactly one wildcard\
+             \n* 'wav=mp3[-b:a 192k],png=webp[-q:v 80]' ffmpeg options in brackets apply only to that rule\
+             \n* 'wav=mp3[-b:a 192k]+flac' converts every input to several outputs, each with its own options\
+             \n* 'docx=pdf@soffice,md=html@pandoc' converts with another program instead of ffmpeg\n\n\
+             Converters: ffmpeg (default), magick (ImageMagick), pandoc, soffice (LibreOffice) and cmd (see '--cmd').\
+             \nOptions in brackets are passed to the converter, magick and pandoc also get '--input-opts' before the input.",
+        value_name = "IN_EXT=OUT_EXT",
+        value_parser = parser_extension_map(), 
+    )]
+    pub extension_map: ExtensionRules,
+
+    /// Named preset (see 'lconvert presets'), arguments given here override its values
+    #[arg(
+        long,
+        value_name = "NAME",
+        long_help =
+            "Named preset, arguments given here override its values (list them with 'lconvert presets')\n\n\
+             Built-in presets: web-mp4, web-webm, podcast-mp3, archive-flac, web-images, gif-preview, whatsapp.\
+             \nMore are read from 'lconvert.toml' in '~/.config/lconvert/' and the current directory (its presets win).\
+             \nKeys are the long names of arguments, 'options' are the ffmpeg options given after '--'.\n\n\
+             Example:\
+             \n| [presets.web-video]\
+             \n| extension-map = \"mov=mp4,mkv=mp4\"\
+             \n| output = \"web/{{stem}}\"\
+             \n| options = [\"-c:v\", \"libx264\", \"-crf\", \"23\"]\
+             \n| n-subprocesses = 2\
+             \n| allow-override = true\
+             \n|\
+             \n| 'lconvert --preset web-video inputs/'",
+    )]
+    pub preset: Option<String>,
+
+    /// ffmpeg options for every output with an extension, can be repeated (e.g. --opts 'mp3:-b:a 192k')
+    #[arg(
+        long = "opts",
+        value_name = "OUT_EXT:OPTIONS",
+        value_parser = parser_extension_options(),
+        long_help =
+            "ffmpeg options for every output with an extension, can be repeated\n\n\
+             Options are added after the options given after '--' and before the options of a rule ('-m wav=mp3[...]').\
+             \nQuotes keep whitespace inside an option.\n\n\
+             Example:\
+             \n| 'lconvert -o out_dir -m wav=mp3,png=webp --opts \"mp3:-b:a 192k\" --opts \"webp:-q:v 80\" input_dir'",
+    )]
+    pub extension_options: Vec<(String, Vec<String>)>,
+
+    /// ffmpeg options placed before '-i', can be repeated (e.g. --input-opts '-ss 30 -t 10')
+    #[arg(
+        long = "input-opts",
+        value_name = "OPTIONS",
+        allow_hyphen_values = true,
+        value_parser = parser_input_options(),
+        long_help =
+            "ffmpeg options placed before '-i', can be repeated\n\n\
+             Input options such as '-ss' and '-t' (fast seeking), '-f', '-r', '-hwaccel' or '-itsoffset'\
+             \nonly work before the input. They can have the same placeholders as output patterns.\n\n\
+             Example:\
+             \n| 'lconvert -o out_dir -m mp4=gif --input-opts \"-ss 30 -t 5\" input_file.mp4'\
+             \n| Expands to:\n|\
+             \n| 'ffmpeg -hide_banner -y -loglevel error -progress - -nostats -ss 30 -t 5 -i input_file.mp4 out_dir/.input_file.4242.lconvert-tmp.gif'",
+    )]
+    pub input_options: Vec<String>,
+
+    /// Full ffmpeg command with {{input}} and {{output}}, for every rule or some (e.g. --template 'wav=mp3:ffmpeg ...')
+    #[arg(
+        long = "template",
+        value_name = "[IN_EXT[=OUT_EXT]:]COMMAND",
+        allow_hyphen_values = true,
+        value_parser = parser_command_template(),
+        long_help =
+            "Full ffmpeg command with {{input}} and {{output}} markers, can be repeated\n\n\
+             Replaces the arguments lconvert builds, for conversions that need extra inputs, '-filter_complex'\
+             \nor '-map'. A template prefixed with an extension map key ('wav:ffmpeg ...') is used only for its rules,\
+             \none prefixed with a rule ('wav=mp3:ffmpeg ...') only for that rule, others for every rule without its\
+             \nown template. Options given with '--', '--opts', '--input-opts'\
+             \nand in the extension map are not used with a template. Arguments can have the same placeholders\
+             \nas output patterns. lconvert still adds the flags it needs for progress and overriding the temporary file.\n\n\
+             Example:\
+             \n| 'lconvert -o out_dir -m mp4 --template \"ffmpeg -i {{input}} -vf scale=-2:720 {{output}}\" input_dir'\
+             \n| Expands to:\n|\
+             \n| 'ffmpeg -hide_banner -y -loglevel error -progress - -nostats -i input_dir/a.mp4 -vf scale=-2:720 out_dir/.a.4242.lconvert-tmp.mp4'",
+    )]
+    pub command_templates: Vec<(Option<String>, Vec<String>)>,
+
+    /// Command with {{input}} and {{output}} for rules using the 'cmd' converter (e.g. -m 'svg=png@cmd')
+    #[arg(
+        long = "cmd",
+        value_name = "[IN_EXT[=OUT_EXT]:]COMMAND",
+        allow_hyphen_values = true,
+        value_parser = parser_converter_command(),
+        long_help =
+            "Command with {{input}} and {{output}} markers for rules using the 'cmd' converter, can be repeated\n\n\
+             Runs any program instead of ffmpeg. A command prefixed with an extension map key ('svg:rsvg-convert ...')\
+             \nis used only for its rules, one prefixed with a rule ('svg=png:rsvg-convert ...') only for that rule,\
+             \nothers for every 'cmd' rule without its own command. Arguments can have\
+             \nthe same placeholders as output patterns. The program must write {{output}}, it is then moved to the output file.\n\n\
+             Example:\
+             \n| 'lconvert -o out_dir -m svg=png@cmd --cmd \"rsvg-convert -w 512 -o {{output}} {{input}}\" input_dir'\
+             \n| Expands to:\n|\
+             \n| 'rsvg-convert -w 512 -o out_dir/.a.4242.lconvert-tmp.png input_dir/a.svg'",
+    )]
+    pub converter_commands: Vec<(Option<String>, Vec<String>)>,
+
     /// Max number of concurent ffmpeg processes
     #[arg(
         short,
@@ -139,6 +574,14 @@ pub struct Arguments {
     )]
     pub n_subprocesses: u32,
 
+    /// Max number of concurent ffprobe processes [default: same as --n-subprocesses]
+    #[arg(
+        long,
+        value_parser = value_parser!(u32).range(1..),
+        value_name = "N",
+    )]
+    pub n_probes: Option<u32>,
+
     /// Path to ffmpeg executable
     #[arg(
         long,
@@ -157,33 +600,53 @@ pub struct Arguments {
     )]
     pub ffprobe_path: PathBuf,
 
-    /// Disables appending {{tree}}/{{file}} to output patterns with no placeholders
+    /// Make extension map case sensetive
     #[arg(
-        short = 'd',
+        short,
         long,
+        overrides_with = "no_case_sensitive",
     )]
-    pub disable_pattern_append: bool,
+    pub case_sensitive: bool,
 
+    /// Undo '-c' of a preset
+    #[arg(long, overrides_with = "case_sensitive")]
+    pub no_case_sensitive: bool,
 
-    /// Make extension map case sensetive
+    /// Directory for unfinished outputs (default: hidden file next to each output)
     #[arg(
-        short,
         long,
+        value_name = "DIR",
+        value_parser = |s: &str| absolute(s),
+        value_hint = ValueHint::DirPath,
     )]
-    pub case_sensitive: bool,
+    pub tmp_dir: Option<PathBuf>,
 
-    /// Allow ffmpeg to override files
+    /// Stop starting new conversions after the first one fails
     #[arg(
-        short = 'y',
         long,
     )]
-    pub allow_override: bool,
+    pub fail_fast: bool,
+
+    /// Write a JSON report of every job to this file after the run
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+    )]
+    pub report: Option<PathBuf>,
+
+    /// Print every planned job without running ffmpeg or creating any files
+    #[arg(
+        long,
+    )]
+    pub dry_run: bool,
 
     /// Custom ffmpeg options to apply to every file (see example with '--help')
     #[arg(
         last = true,
         num_args = 0..,
         name = "FFMPEG_OPTIONS",
+        value_parser = parser_ffmpeg_option(),
         long_help = 
             "Custom ffmpeg options to apply to every file\n\n\
              Warning! Some options (such specifing output pipe) may result in upredictable beviour\n\n\
@@ -191,35 +654,176 @@ pub struct Arguments {
              \n| 'lconvert -o out_dir -m wav=mp3 input_file.wav -- -ab 128KB'\
              \n|                                     Custom option ^^^^^^^^^\
              \n| Expands to:\n|\
-             \n| 'ffmpeg -hide_banner -n -loglevel error -progress - -nostats -i input_file.wav -ab 128KB out_dir/input_file.mp3'\
-             \n|                                                                  Custom option ^^^^^^^^^"
+             \n| 'ffmpeg -hide_banner -y -loglevel error -progress - -nostats -i input_file.wav -ab 128KB out_dir/.input_file.4242.lconvert-tmp.mp3'\
+             \n|                                                                  Custom option ^^^^^^^^^\
+             \n|\
+             \n| The temporary file is renamed to 'out_dir/input_file.mp3' once ffmpeg succeeds\n\n\
+             Options can have the same placeholders as output patterns (except {{unique-suffix}}), filled for every file:\
+             \n| 'lconvert -o out_dir -m wav=mp3 music -- -metadata title={{stem}} -metadata \"comment=from {{file}}\"'"
     )]
     pub ffmpeg_str_options: Vec<String>,
 }
 
-impl Arguments {
-    pub fn get_glob_expanded_input_files(&self) -> Vec<PathBuf> {
-        let mut results: Vec<PathBuf> = Vec::new();
+impl ConvertArguments {
+    pub fn get_n_probes(&self) -> u32 {
+        self.n_probes.unwrap_or(self.n_subprocesses)
+    }
 
-        for input_file in self.input_files.iter() {
-            if input_file.exists() {
-                results.push(input_file.clone());
-            } else {
-                results.extend(glob(input_file.to_str().unwrap())
-                    .expect("Validated this during parsing")
-                    .into_iter()
-                    .collect::<Result<Vec<PathBuf>, GlobError>>()
-                    .expect("Validated this during parsing")
-                );
+    /// The last '--template' without an extension map key
+    pub fn get_command_template(&self) -> Option<Vec<String>> {
+        self.command_templates.iter().rev().find(|(key, _)| key.is_none()).map(|(_, args)| args.clone())
+    }
+
+    /// Templates by rule ('IN_EXT=OUT_EXT'). A template for the rule wins over one for its extension map key,
+    /// otherwise the last one wins. Fails on templates that match no rule converted with ffmpeg
+    pub fn get_rule_command_templates(&self) -> Result<HashMap<String, Vec<String>>, anyhow::Error> {
+        let eq = |a: &str, b: &str| if self.case_sensitive { a == b } else { a.eq_ignore_ascii_case(b) };
+        let keyed = || self.command_templates.iter().rev().filter_map(|(key, args)| Some((key.as_deref()?, args)));
+        let rules: Vec<(&str, String)> = self.extension_map.extension_map.iter()
+            .flat_map(|(input, outputs)| outputs.iter().map(move |output| (input.as_str(), format!("{input}={output}"))))
+            .collect();
+        let uses_ffmpeg = |rule: &str| self.extension_map.converters.get(rule).is_none_or(|x| x == "ffmpeg");
+
+        for (key, _) in keyed() {
+            let mut matched: Vec<&str> = rules.iter().filter(|(input, rule)| eq(key, input) || eq(key, rule)).map(|(_, rule)| rule.as_str()).collect();
+            matched.sort();
+            if matched.is_empty() {
+                anyhow::bail!("Template for '{key}' matches no extension map rule");
+            }
+            if !matched.iter().any(|x| uses_ffmpeg(x)) {
+                anyhow::bail!("Template for '{key}' matches only rules that are not converted with ffmpeg: '{}'", matched.join(","));
             }
         }
-        results
+
+        let mut templates = HashMap::new();
+        for (input, rule) in rules.iter().filter(|(_, rule)| uses_ffmpeg(rule)) {
+            let template = keyed()
+                .find(|(key, _)| eq(key, rule))
+                .or_else(|| keyed().find(|(key, _)| eq(key, input)));
+            if let Some((_, args)) = template {
+                templates.insert(rule.clone(), args.clone());
+            }
+        }
+        Ok(templates)
+    }
+
+    /// Options of every '--input-opts', in order
+    pub fn get_input_options(&self) -> Vec<String> {
+        self.input_options
+            .iter()
+            .flat_map(|x| split_options(x).expect("Checked by parser_input_options"))
+            .collect()
     }
+
+    /// Converters by rule ('IN_EXT=OUT_EXT'), for outputs that picked one with '@CONVERTER'. A '--cmd' for the rule wins
+    /// over one for its extension map key, then over one without a key. Fails on commands that match no 'cmd' rule
+    pub fn get_converters(&self) -> Result<HashMap<String, Arc<dyn Converter>>, anyhow::Error> {
+        let eq = |a: &str, b: &str| if self.case_sensitive { a == b } else { a.eq_ignore_ascii_case(b) };
+        let keyed = || self.converter_commands.iter().rev().filter_map(|(key, args)| Some((key.as_deref()?, args)));
+        fn input_key(rule: &str) -> &str {
+            rule.split_once('=').map_or(rule, |(key, _)| key)
+        }
+
+        for (key, _) in keyed() {
+            let matches_rule = self.extension_map.converters
+                .iter()
+                .any(|(rule, name)| name == "cmd" && (eq(key, rule) || eq(key, input_key(rule))));
+            if !matches_rule {
+                anyhow::bail!("Command for '{key}' matches no extension map rule using the 'cmd' converter");
+            }
+        }
+
+        let command = |rule: &str| keyed()
+            .find(|(key, _)| eq(key, rule))
+            .or_else(|| keyed().find(|(key, _)| eq(key, input_key(rule))))
+            .map(|(_, args)| args)
+            .or(self.converter_commands.iter().rev().find(|(x, _)| x.is_none()).map(|(_, args)| args))
+            .map(|x| x.as_slice());
+
+        self.extension_map.converters
+            .iter()
+            .map(|(rule, name)| {
+                let converter = converter_by_name(name, command(rule))
+                    .with_context(|| format!("Invalid converter of rule '{rule}@{name}'"))?;
+                Ok((rule.clone(), converter))
+            })
+            .collect()
+    }
+
+    /// Options of every '--opts', options of the same extension are joined
+    pub fn get_extension_options(&self) -> OptionsMap {
+        let mut options = OptionsMap::new();
+        for (extension, extension_options) in self.extension_options.iter() {
+            let extension = if self.case_sensitive { extension.clone() } else { extension.to_lowercase() };
+            options.entry(extension).or_default().extend(extension_options.iter().cloned());
+        }
+        options
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Keep OUTPUT an exact converted mirror of SOURCE
+    #[command(
+        long_about =
+            "Keep OUTPUT an exact converted mirror of SOURCE\n\n\
+             Converts new files and files changed since the last sync, and removes outputs\n\
+             whose source file no longer exists (after confirmation, or with '--delete').\n\
+             Every file in OUTPUT that is not an output of a file in SOURCE is removed.",
+    )]
+    Sync(Box<SyncArguments>),
+    /// List built-in presets and presets of 'lconvert.toml' (see '--preset')
+    Presets,
+}
+
+#[derive(Args, Debug)]
+pub struct SyncArguments {
+    /// Source directory
+    #[arg(
+        value_parser = |s: &str| absolute(s),
+        value_hint = ValueHint::DirPath,
+    )]
+    pub source: PathBuf,
+
+    /// Mirror directory
+    #[arg(
+        short = 'o',
+        long,
+        value_name = "DIR",
+        value_parser = |s: &str| absolute(s),
+        value_hint = ValueHint::DirPath,
+    )]
+    pub output: PathBuf,
+
+    /// Remove orphaned outputs without asking
+    #[arg(
+        long,
+    )]
+    pub delete: bool,
+
+    #[command(flatten)]
+    pub convert: ConvertArguments,
+}
+
+/// What the placeholders of an output pattern are filled from, for a single input file
+#[derive(Debug, Clone, Copy)]
+pub struct PlaceholderSource<'a> {
+    pub input_file: &'a Path,
+    pub output_extension: &'a str,
+    /// Parent hierarchy of the input file, relative to the walked input directory
+    pub tree: &'a Option<PathBuf>,
+    pub media_info: Option<&'a MediaInfo>,
+    /// Number of the input, counting from 1
+    pub index: usize,
+    /// Hash of the input file, computed by the first {{hash}} and shared by every output of the input
+    pub hash: &'a OnceCell<String>,
 }
 
 #[derive(Debug)]
 pub struct OutputPattern {
-    pattern: PathBuf
+    pattern: PathBuf,
+    /// Filled into {{now}}, the same for every file of a run
+    started: DateTime<Local>,
 }
 
 impl OutputPattern {
@@ -230,30 +834,139 @@ impl OutputPattern {
     pub const TREE: &'static str = "{{tree}}";
     pub const PARENT: &'static str = "{{parent}}";
     pub const UNIQUE_SUFFIX: &'static str = "{{unique-suffix}}";
+    /// Input file in a command template, filled when ffmpeg is spawned
+    pub const INPUT: &'static str = "{{input}}";
+    /// File ffmpeg writes to in a command template, filled when ffmpeg is spawned
+    pub const OUTPUT: &'static str = "{{output}}";
+    pub const TAG: &'static str = "{{tag:<NAME>}}";
+    pub const WIDTH: &'static str = "{{width}}";
+    pub const HEIGHT: &'static str = "{{height}}";
+    pub const CODEC: &'static str = "{{codec}}";
+    pub const DURATION: &'static str = "{{duration}}";
+    pub const INDEX: &'static str = "{{index}}";
+    pub const DATE: &'static str = "{{date:<FORMAT>}}";
+    pub const NOW: &'static str = "{{now}}";
+    pub const HASH: &'static str = "{{hash:<N>}}";
+
+    /// Used for a missing metadata value when the placeholder has no fallback
+    pub const DEFAULT_FALLBACK: &'static str = "Unknown";
+
+    /// Every placeholder name, the part before the first ':'
+    const NAMES: [&'static str; 16] = [
+        "file", "stem", "in-ext", "out-ext", "tree", "parent", "unique-suffix", "index",
+        "date", "now", "hash", "tag", "width", "height", "codec", "duration",
+    ];
+
+    /// Placeholders filled from probed media info
+    const METADATA_NAMES: [&'static str; 6] = ["tag", "width", "height", "codec", "duration", "date"];
+
+    /// Number of hex characters of {{hash}} without a length
+    pub const DEFAULT_HASH_LENGTH: usize = 8;
 
-    pub fn new(pattern: PathBuf) -> Self {
-        Self { pattern }
+    /// Largest zero padded width, e.g. '{{index:10}}'
+    pub const MAX_PAD_WIDTH: usize = 10;
+
+    /// Fails on unbalanced braces and unknown placeholders or filters
+    pub fn new(pattern: PathBuf) -> Result<Self, anyhow::Error> {
+        for placeholder in Template::parse(&pattern.to_string_lossy())?.placeholders() {
+            Self::validate(placeholder).with_context(|| format!("Invalid placeholder: '{{{{{}}}}}'", placeholder.source))?;
+        }
+        Ok(Self { pattern, started: Local::now() })
+    }
+
+    /// Checks the name and the arguments after it, filters are checked when parsing
+    fn validate(placeholder: &Placeholder) -> Result<(), anyhow::Error> {
+        let name = placeholder.name.as_str();
+        if !Self::NAMES.contains(&name) {
+            match nearest(name, &Self::NAMES) {
+                Some(nearest) => anyhow::bail!("Unknown placeholder '{name}', did you mean '{{{{{nearest}}}}}'?"),
+                None => anyhow::bail!("Unknown placeholder '{name}', see '--help' for the list of placeholders"),
+            }
+        }
+
+        let numbers = match name {
+            "date" | "now" => return check_date_format(&placeholder.args.join(":")),
+            "unique-suffix" => &[][..],
+            "tag" => match placeholder.args.split_first() {
+                Some((tag, numbers)) if !tag.is_empty() => numbers,
+                _ => anyhow::bail!("Tag name is missing, e.g. '{{{{tag:artist}}}}'"),
+            },
+            _ => &placeholder.args[..],
+        };
+        match numbers {
+            [] => Ok(()),
+            [number] => match number.parse::<usize>().with_context(|| format!("Expected a number after ':', got '{number}'"))? {
+                width if name != "hash" && width > Self::MAX_PAD_WIDTH => anyhow::bail!("Zero padded width can be at most {}, got {width}", Self::MAX_PAD_WIDTH),
+                _ => Ok(()),
+            },
+            _ => anyhow::bail!("Too many arguments after '{name}'"),
+        }
     }
 
     pub fn has_blanks(&self) -> bool {
-        let string = self.pattern.to_string_lossy();
-        string.contains(Self::FILE) ||
-        string.contains(Self::STEM) ||
-        string.contains(Self::IN_EXT) ||
-        string.contains(Self::OUT_EXT) ||
-        string.contains(Self::TREE) ||
-        string.contains(Self::PARENT) ||
-        string.contains(Self::UNIQUE_SUFFIX)
+        Template::parse(&self.pattern.to_string_lossy()).is_ok_and(|x| x.placeholders().next().is_some())
+    }
+
+    /// Pattern has placeholders filled from probed media info (tags, resolution, ...)
+    pub fn needs_media_info(&self) -> bool {
+        Self::text_needs_media_info(&self.pattern.to_string_lossy())
+    }
+
+    /// Text (e.g. an ffmpeg option) has placeholders filled from probed media info
+    pub fn text_needs_media_info(text: &str) -> bool {
+        Template::parse(text).is_ok_and(|x| x.placeholders().any(|x| Self::METADATA_NAMES.contains(&x.name.as_str())))
+    }
+
+    /// Checks placeholders in text other than the output pattern, e.g. ffmpeg options
+    pub fn validate_text(text: &str) -> Result<(), anyhow::Error> {
+        for placeholder in Template::parse(text)?.placeholders() {
+            if placeholder.name == "unique-suffix" {
+                anyhow::bail!("'{}' can only be used in output patterns", Self::UNIQUE_SUFFIX);
+            }
+            Self::validate(placeholder).with_context(|| format!("Invalid placeholder: '{{{{{}}}}}'", placeholder.source))?;
+        }
+        Ok(())
+    }
+
+    /// Checks a command template (ffmpeg arguments with {{input}} and {{output}})
+    pub fn validate_command(args: &[String]) -> Result<(), anyhow::Error> {
+        let mut has_output = false;
+
+        for arg in args.iter() {
+            for placeholder in Template::parse(arg)?.placeholders() {
+                if !matches!(placeholder.name.as_str(), "input" | "output") {
+                    Self::validate_text(&format!("{{{{{}}}}}", placeholder.source))?;
+                    continue;
+                }
+                if placeholder.source != placeholder.name {
+                    anyhow::bail!("'{{{{{}}}}}' can not have arguments, a fallback or filters", placeholder.name);
+                }
+                has_output |= placeholder.name == "output";
+            }
+        }
+
+        if !has_output {
+            anyhow::bail!("Command template has no '{}'", Self::OUTPUT);
+        }
+        Ok(())
+    }
+
+    /// Fills the placeholders of text other than the output pattern, e.g. ffmpeg options.
+    /// Unlike in output patterns, values are used as they are, not made safe for file names
+    pub fn fill_text(&self, text: &str, source: &PlaceholderSource) -> Result<String, anyhow::Error> {
+        Template::parse(text)?.render(Self::DEFAULT_FALLBACK, |placeholder| {
+            if placeholder.name == "unique-suffix" {
+                anyhow::bail!("'{}' can only be used in output patterns", Self::UNIQUE_SUFFIX);
+            }
+            self.placeholder_value(placeholder, source, false)
+                .with_context(|| format!("Could not fill placeholder: '{{{{{}}}}}'", placeholder.source))
+        })
     }
 
     pub fn fill_blanks(
         &self, 
-        input_file: &Path, 
-        extension_map: &ExtensionMap, 
-        input_extension: &str, 
-        tree: &Option<PathBuf>,
-        ffmpeg_options: &Vec<FFmpegOptions>,
-        allow_override: bool,
+        source: &PlaceholderSource,
+        ffmpeg_options: &[FFmpegOptions],
         disable_pattern_append: bool
     ) -> Result<PathBuf, anyhow::Error> {
         let output_pattern = if !self.has_blanks() && !disable_pattern_append {
@@ -262,36 +975,112 @@ impl OutputPattern {
             self.pattern.to_owned()
         };
 
-        let output_pattern = output_pattern.to_string_lossy()
-            .replace(
-                Self::STEM, 
-                input_file.file_stem().with_context(|| format!("Could not get file_name: '{}'", input_file.display()))?.to_str().unwrap()
-            ).replace(
-                Self::FILE, 
-                input_file.file_name().with_context(|| format!("Could not get file_name: '{}'", input_file.display()))?.to_str().unwrap()
-            ).replace(
-                Self::IN_EXT,
-                input_file.extension().with_context(|| format!("Could not get extension: '{}'", input_file.display()))?.to_str().unwrap()
-            ).replace(
-                Self::OUT_EXT,
-                &extension_map[input_extension]
-            ).replace(
-                Self::PARENT,
-                input_file.parent().with_context(|| format!("Could not get parent: '{}'", input_file.display()))?
-                          .file_name().with_context(|| format!("Could not get file_name: '{}'", input_file.display()))?.to_str().unwrap()
-            ).replace(
-                Self::TREE, 
-                if let Some(t) = &tree { t.to_str().unwrap() } else { "" }
-            );
+        let template = Template::parse(&output_pattern.to_string_lossy())?;
+        let output_pattern = template.render(Self::DEFAULT_FALLBACK, |placeholder| {
+            self.placeholder_value(placeholder, source, true)
+                .with_context(|| format!("Could not fill placeholder: '{{{{{}}}}}'", placeholder.source))
+        })?;
 
         let mut output_file = absolute(Path::new(&output_pattern))?;
-        output_file.set_extension(&extension_map[input_extension]);
-        output_file = Self::replace_uniques(output_file, &ffmpeg_options, allow_override);
+        output_file.set_extension(source.output_extension);
+        output_file = Self::replace_uniques(output_file, ffmpeg_options);
 
         Ok(output_file)
     }
 
-    fn replace_uniques(mut output_file: PathBuf, ffmpeg_options: &Vec<FFmpegOptions>, _allow_override: bool) -> PathBuf {
+    /// Fills {{unique-suffix}} in the leading directories that have no other placeholders,
+    /// so files planned later (e.g. while watching) land in the same directory as the first ones
+    pub fn resolve_unique_dirs(&self) -> Result<Self, anyhow::Error> {
+        let pattern = absolute(&self.pattern)?;
+        let components: Vec<_> = pattern.iter().collect();
+        let mut resolved = PathBuf::new();
+        let mut i = 0;
+
+        while i + 1 < components.len() {
+            let component = components[i].to_string_lossy();
+            if component.replace(Self::UNIQUE_SUFFIX, "").contains("{{") {
+                break;
+            }
+
+            let mut o = component.replace(Self::UNIQUE_SUFFIX, "");
+            let mut num = 1;
+            while component.contains(Self::UNIQUE_SUFFIX) && resolved.join(&o).exists() {
+                o = component.replace(Self::UNIQUE_SUFFIX, &format!("_{}", num));
+                num += 1;
+            }
+            resolved.push(o);
+            i += 1;
+        }
+
+        let resolved = Self { pattern: resolved.join(components[i..].iter().collect::<PathBuf>()), started: self.started };
+
+        // A pattern with no placeholders left would get {{tree}}/{{file}} appended
+        if self.has_blanks() && !resolved.has_blanks() {
+            return Ok(Self { pattern, started: self.started });
+        }
+        Ok(resolved)
+    }
+
+    /// Value of a single placeholder, `None` if the input has no such metadata.
+    /// `{{unique-suffix}}` is kept to be filled by `replace_uniques`.
+    /// With `sanitize`, characters that can not be in a file name are replaced in metadata values
+    fn placeholder_value(&self, placeholder: &Placeholder, source: &PlaceholderSource, sanitize: bool) -> Result<Option<String>, anyhow::Error> {
+        let PlaceholderSource { input_file, output_extension, tree, media_info, index, hash } = *source;
+        let file_name = || input_file.file_name().with_context(|| format!("Could not get file_name: '{}'", input_file.display()));
+        let mut args = placeholder.args.iter();
+
+        let value = match placeholder.name.as_str() {
+            "file" => Some(file_name()?.to_string_lossy().into_owned()),
+            "stem" => Some(input_file.file_stem().with_context(|| format!("Could not get file_name: '{}'", input_file.display()))?.to_string_lossy().into_owned()),
+            "in-ext" => Some(input_file.extension().with_context(|| format!("Could not get extension: '{}'", input_file.display()))?.to_string_lossy().into_owned()),
+            "out-ext" => Some(output_extension.to_owned()),
+            "parent" => Some(input_file.parent().with_context(|| format!("Could not get parent: '{}'", input_file.display()))?
+                .file_name().with_context(|| format!("Could not get file_name: '{}'", input_file.display()))?.to_string_lossy().into_owned()),
+            "tree" => Some(tree.as_ref().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default()),
+            "unique-suffix" => return Ok(Some(Self::UNIQUE_SUFFIX.to_owned())),
+            "input" => return Ok(Some(Self::INPUT.to_owned())),
+            "output" => return Ok(Some(Self::OUTPUT.to_owned())),
+            "index" => Some(index.to_string()),
+            "date" => Some(format_date(input_date(input_file, media_info)?, &args.by_ref().cloned().collect::<Vec<_>>().join(":"), "%Y-%m-%d")?),
+            "now" => Some(format_date(self.started, &args.by_ref().cloned().collect::<Vec<_>>().join(":"), "%Y-%m-%d_%H-%M-%S")?),
+            "hash" => {
+                let length = match args.next() {
+                    Some(length) => length.parse::<usize>().with_context(|| format!("Invalid hash length: '{length}'"))?,
+                    None => Self::DEFAULT_HASH_LENGTH,
+                };
+                Some(hash_file(input_file, hash)?.chars().take(length).collect())
+            },
+            "width" => media_info.and_then(|x| x.video()?.width).map(|x| x.to_string()),
+            "height" => media_info.and_then(|x| x.video()?.height).map(|x| x.to_string()),
+            "codec" => media_info.and_then(|x| x.video().or(x.audio())?.codec.clone()),
+            "duration" => media_info.and_then(|x| x.duration).map(|x| format!("{}", x.round() as u64)),
+            "tag" => {
+                let name = args.next().filter(|x| !x.is_empty()).context("Tag name is missing, e.g. '{{tag:artist}}'")?;
+                media_info.and_then(|x| x.tag(name)).map(str::to_owned)
+            },
+            name => anyhow::bail!("Unknown placeholder '{name}'"),
+        };
+
+        let width = match args.next() {
+            Some(width) => Some(width.parse::<usize>().with_context(|| format!("Invalid zero padded width: '{width}'"))?),
+            None => None,
+        };
+
+        let value = if Self::METADATA_NAMES.contains(&placeholder.name.as_str()) || placeholder.name == "now" {
+            value
+                .map(|x| if sanitize { sanitize_file_name(x.trim()) } else { x.trim().to_owned() })
+                .filter(|x| !x.is_empty())
+        } else {
+            value
+        };
+
+        Ok(match width {
+            Some(width) => value.map(|x| zero_pad(&x, width)),
+            None => value,
+        })
+    }
+
+    fn replace_uniques(mut output_file: PathBuf, ffmpeg_options: &[FFmpegOptions]) -> PathBuf {
         let mut flag = true;
 
         while flag { for (i, (first, second)) in get_components(&output_file).iter().enumerate() {
@@ -321,6 +1110,58 @@ impl OutputPattern {
     }
 }
 
+/// Replaces characters that can not be in a file name on common file systems
+fn sanitize_file_name(value: &str) -> String {
+    value.chars().map(|c| if c.is_control() || r#"/\:*?"<>|"#.contains(c) { '_' } else { c }).collect()
+}
+
+/// The `creation_time` tag of the input, or its modification time
+fn input_date(input_file: &Path, media_info: Option<&MediaInfo>) -> Result<DateTime<Local>, anyhow::Error> {
+    if let Some(created) = media_info.and_then(|x| x.tag("creation_time")).and_then(|x| DateTime::parse_from_rfc3339(x).ok()) {
+        return Ok(created.with_timezone(&Local));
+    }
+    let modified = metadata(input_file)
+        .and_then(|x| x.modified())
+        .with_context(|| format!("Could not read modification time: '{}'", input_file.display()))?;
+    Ok(modified.into())
+}
+
+/// Fails on unknown or incomplete strftime specifiers, e.g. '%Q'
+fn check_date_format(format: &str) -> Result<(), anyhow::Error> {
+    if StrftimeItems::new(format).any(|x| matches!(x, Item::Error)) {
+        anyhow::bail!("Invalid date format: '{format}'");
+    }
+    Ok(())
+}
+
+/// Formats with a strftime format (e.g. '%Y-%m-%d'), `default` if `format` is empty
+fn format_date(date: DateTime<Local>, format: &str, default: &str) -> Result<String, anyhow::Error> {
+    let format = if format.is_empty() { default } else { format };
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format)).map_err(|_| anyhow::anyhow!("Invalid date format: '{format}'"))?;
+    Ok(formatted)
+}
+
+/// Hex encoded blake3 hash of the file content, read only if `hash` is not filled yet
+fn hash_file<'a>(input_file: &Path, hash: &'a OnceCell<String>) -> Result<&'a str, anyhow::Error> {
+    if let Some(hash) = hash.get() {
+        return Ok(hash);
+    }
+    let file = File::open(input_file).with_context(|| format!("Could not open file: '{}'", input_file.display()))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(file).with_context(|| format!("Could not read file: '{}'", input_file.display()))?;
+    Ok(hash.get_or_init(|| hasher.finalize().to_hex().to_string()))
+}
+
+/// Pads the number a value starts with ('3' or '3/12' for track tags) with zeros
+fn zero_pad(value: &str, width: usize) -> String {
+    let digits = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    match value[..digits].parse::<u64>() {
+        Ok(number) => format!("{number:0width$}"),
+        Err(_) => value.to_owned(),
+    }
+}
+
 pub fn get_longest_common_path(mut paths: Vec<&Path>) -> Option<PathBuf> {
     if paths.is_empty() {
         return None;
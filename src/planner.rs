@@ -0,0 +1,331 @@
+use std::{cell::OnceCell, collections::HashMap, fs::{metadata, read_dir, DirEntry}, path::{Path, PathBuf}, sync::Arc};
+use anyhow::Context;
+use crate::converter::{Converter, FFmpegConverter};
+use crate::ffmpeg::FFmpegOptions;
+use crate::media::{probe_files, MediaInfo};
+use crate::parser::{ExtensionMap, OptionsMap, OutputPattern, PlaceholderSource};
+
+/// A single planned conversion of one input file to one output file
+pub type Job = FFmpegOptions;
+
+/// Why an input file did not produce a job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// No extension map rule (and no wildcard) matches the input extension
+    NoMatchingRule,
+    /// Output file already exists ('--skip-existing')
+    OutputExists,
+    /// Output file is newer than the input file ('--update')
+    UpToDate,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { match self {
+        SkipReason::NoMatchingRule => write!(f, "no matching extension map rule"),
+        SkipReason::OutputExists => write!(f, "output already exists"),
+        SkipReason::UpToDate => write!(f, "output is up to date"),
+    }}
+}
+
+#[derive(Debug)]
+pub struct Skipped {
+    pub input_file: PathBuf,
+    /// Output the input would have been converted to, if it matched a rule
+    pub output_file: Option<PathBuf>,
+    pub reason: SkipReason,
+}
+
+/// Result of planning: jobs to run and inputs that were left out
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub jobs: Vec<Job>,
+    pub skipped: Vec<Skipped>,
+    /// Inputs matched by an extension map rule so far, the {{index}} of the last one
+    pub n_inputs: usize,
+}
+
+impl Plan {
+    /// Some job converts with ffmpeg, so ffmpeg and ffprobe must be found (see [`crate::init_ffmpeg_paths`])
+    pub fn uses_ffmpeg(&self) -> bool {
+        self.jobs.iter().any(|x| x.converter.name() == FFmpegConverter.name())
+    }
+}
+
+/// Turns input files and directories into jobs using an extension map and an output pattern
+#[derive(Debug)]
+pub struct Planner {
+    pub output_pattern: OutputPattern,
+    pub extension_map: ExtensionMap,
+    /// Options for every job, filled with the same placeholders as the output pattern
+    pub ffmpeg_str_options: Vec<String>,
+    /// Options placed before '-i' for every job, filled like `ffmpeg_str_options`
+    pub ffmpeg_input_options: Vec<String>,
+    /// Options for jobs by output extension, added after `ffmpeg_str_options`
+    pub extension_options: OptionsMap,
+    /// Options for jobs by rule ('IN_EXT=OUT_EXT', IN_EXT being the extension map key), added last
+    pub rule_options: OptionsMap,
+    /// ffmpeg arguments with {{input}} and {{output}} replacing every option, for rules without their own template
+    pub command_template: Option<Vec<String>>,
+    /// Command templates by rule ('IN_EXT=OUT_EXT')
+    pub rule_command_templates: HashMap<String, Vec<String>>,
+    /// Converters by rule ('IN_EXT=OUT_EXT'), ffmpeg for rules without one
+    pub converters: HashMap<String, Arc<dyn Converter>>,
+    pub case_sensitive: bool,
+    pub allow_override: bool,
+    pub disable_pattern_append: bool,
+    /// Where ffmpeg writes temporary files, next to each output if `None`
+    pub tmp_dir: Option<PathBuf>,
+    /// Don't plan jobs whose output file already exists
+    pub skip_existing: bool,
+    /// Don't plan jobs whose output file is newer than the input, override outdated outputs
+    pub update: bool,
+    /// Max number of concurrent ffprobe processes, when the output pattern needs media info
+    pub n_probes: u32,
+}
+
+impl Planner {
+    pub fn new(output_pattern: OutputPattern, extension_map: ExtensionMap) -> Self {
+        Self {
+            output_pattern,
+            extension_map,
+            ffmpeg_str_options: Vec::new(),
+            ffmpeg_input_options: Vec::new(),
+            extension_options: OptionsMap::new(),
+            rule_options: OptionsMap::new(),
+            command_template: None,
+            rule_command_templates: HashMap::new(),
+            converters: HashMap::new(),
+            case_sensitive: false,
+            allow_override: false,
+            disable_pattern_append: false,
+            tmp_dir: None,
+            skip_existing: false,
+            update: false,
+            n_probes: 4,
+        }
+    }
+
+    /// Walks every input (recursing into directories) and returns a job for each file matched by the extension map.
+    /// Nothing is written to disk. Inputs are probed first (concurrently) only if the output pattern needs media info
+    pub fn plan(&self, input_files: Vec<PathBuf>) -> Result<Plan, anyhow::Error> {
+        let mut files = Vec::new();
+        collect_files(input_files, None, &mut files)?;
+
+        let media_infos = if self.needs_media_info() {
+            probe_files(files.iter().map(|(x, _)| x.as_path()).collect(), self.n_probes)
+        } else {
+            vec![None; files.len()]
+        };
+
+        let mut plan = Plan::default();
+        for ((input_file, tree), media_info) in files.into_iter().zip(media_infos) {
+            self.plan_file(input_file, &tree, media_info, &mut plan)?;
+        }
+        Ok(plan)
+    }
+
+    /// Plans a single file found under the input directory `root`, as if `root` had been walked,
+    /// adding to an existing plan (its jobs are taken into account for unique suffixes)
+    pub fn plan_in(&self, input_file: PathBuf, root: &Path, plan: &mut Plan) -> Result<(), anyhow::Error> {
+        let relative_parent = input_file
+            .parent()
+            .and_then(|x| x.strip_prefix(root).ok())
+            .with_context(|| format!("File is not inside input directory '{}': '{}'", root.display(), input_file.display()))?;
+        let tree = Path::new(root
+            .file_name()
+            .with_context(|| format!("Could not read file_name: '{}'", root.display()))?
+        ).join(relative_parent);
+
+        let media_info = if self.needs_media_info() {
+            MediaInfo::probe(&input_file).ok()
+        } else {
+            None
+        };
+
+        self.plan_file(input_file, &Some(tree), media_info, plan)
+    }
+
+    fn plan_file(&self, input_file: PathBuf, tree: &Option<PathBuf>, media_info: Option<MediaInfo>, plan: &mut Plan) -> Result<(), anyhow::Error> {
+        let Some(input_extension) = self.match_extension(&input_file)? else {
+            plan.skipped.push(Skipped { input_file, output_file: None, reason: SkipReason::NoMatchingRule });
+            return Ok(());
+        };
+        plan.n_inputs += 1;
+
+        let hash = OnceCell::new();
+        for output_extension in self.extension_map[input_extension].iter() {
+            let source = PlaceholderSource {
+                input_file: &input_file,
+                output_extension,
+                tree,
+                media_info: media_info.as_ref(),
+                index: plan.n_inputs,
+                hash: &hash,
+            };
+            self.plan_output(&source, input_extension, plan)?;
+        }
+        Ok(())
+    }
+
+    /// Plans one of the outputs of an input, `input_extension` is the extension map key that matched it
+    fn plan_output(&self, source: &PlaceholderSource, input_extension: &str, plan: &mut Plan) -> Result<(), anyhow::Error> {
+        let input_file = source.input_file.to_owned();
+        let output_extension = source.output_extension;
+        let rule = format!("{input_extension}={output_extension}");
+
+        let output_file = self.output_pattern.fill_blanks(source, &plan.jobs, self.disable_pattern_append)?;
+        // Both would write the same temporary file and output
+        if let Some(job) = plan.jobs.iter().find(|x| x.output_file == output_file) {
+            anyhow::bail!("'{}' and '{}' would both be converted to '{}'", job.input_file.display(), input_file.display(), output_file.display());
+        }
+        let ffmpeg_str_options = self.ffmpeg_str_options
+            .iter()
+            .chain(self.options_for(&self.extension_options, output_extension))
+            .chain(self.rule_options.get(&rule).into_iter().flatten())
+            .map(|x| self.output_pattern.fill_text(x, source))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ffmpeg_input_options = self.ffmpeg_input_options
+            .iter()
+            .map(|x| self.output_pattern.fill_text(x, source))
+            .collect::<Result<Vec<_>, _>>()?;
+        let converter = self.converters.get(&rule);
+        let command_template = match converter.and_then(|x| x.command_template()) {
+            Some(template) => Some(template),
+            None => self.rule_command_templates
+                .get(&rule)
+                .or(self.command_template.as_ref())
+                .map(|x| x.as_slice()),
+        };
+        let command_template = command_template
+            .map(|x| x.iter().map(|x| self.output_pattern.fill_text(x, source)).collect::<Result<Vec<_>, _>>())
+            .transpose()?;
+
+        let mut allow_override = self.allow_override;
+
+        if output_file.exists() {
+            if self.skip_existing {
+                plan.skipped.push(Skipped { input_file, output_file: Some(output_file), reason: SkipReason::OutputExists });
+                return Ok(());
+            }
+            if self.update {
+                if is_up_to_date(&input_file, &output_file)? {
+                    plan.skipped.push(Skipped { input_file, output_file: Some(output_file), reason: SkipReason::UpToDate });
+                    return Ok(());
+                }
+                allow_override = true;
+            }
+        }
+
+        let mut job = FFmpegOptions {
+            tmp_dir: self.tmp_dir.clone(),
+            input_options: ffmpeg_input_options,
+            command_template,
+            converter: converter.cloned().unwrap_or_else(|| Arc::new(FFmpegConverter)),
+            ..FFmpegOptions::new(input_file, output_file, rule, allow_override, ffmpeg_str_options)
+        };
+        if source.media_info.is_some() {
+            job.set_media_info(source.media_info.cloned());
+        }
+        plan.jobs.push(job);
+        Ok(())
+    }
+
+    /// Output pattern or ffmpeg options have placeholders filled from probed media info, planning runs ffprobe
+    pub fn needs_media_info(&self) -> bool {
+        self.output_pattern.needs_media_info() || self.ffmpeg_str_options
+            .iter()
+            .chain(self.ffmpeg_input_options.iter())
+            .chain(self.command_template.iter().flatten())
+            .chain(self.rule_command_templates.values().flatten())
+            .chain(self.converters.values().filter_map(|x| x.command_template()).flatten())
+            .chain(self.extension_options.values().flatten())
+            .chain(self.rule_options.values().flatten())
+            .any(|x| OutputPattern::text_needs_media_info(x))
+    }
+
+    /// Some rule converts with ffmpeg, jobs planned later (e.g. while watching) may need it
+    pub fn uses_ffmpeg(&self) -> bool {
+        self.extension_map.iter().any(|(key, outputs)| outputs.iter().any(|output| self.converters
+            .get(&format!("{key}={output}"))
+            .is_none_or(|x| x.name() == FFmpegConverter.name())
+        ))
+    }
+
+    /// Options of an extension, compared ignoring case unless `case_sensitive`
+    fn options_for<'a>(&self, options: &'a OptionsMap, extension: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        let case_sensitive = self.case_sensitive;
+        options
+            .iter()
+            .filter(move |(key, _)| if case_sensitive { *key == extension } else { key.eq_ignore_ascii_case(extension) })
+            .flat_map(|(_, options)| options.iter())
+    }
+
+    /// Returns the extension map key that applies to the file, if any
+    fn match_extension(&self, input_file: &Path) -> Result<Option<&str>, anyhow::Error> {
+        let mut input_extension = input_file
+            .extension()
+            .with_context(|| format!("File has no extension: '{}'", input_file.display()))?
+            .to_str()
+            .with_context(|| format!("File has non utf-8 fucked up extension: '{}'", input_file.display()))?;
+
+        if !self.case_sensitive { for key in self.extension_map.keys() {
+            if key.to_lowercase().eq(&input_extension.to_lowercase()) {
+                input_extension = key;
+                break;
+            }
+        }}
+
+        if !self.extension_map.contains_key(input_extension) {
+            if self.extension_map.contains_key("*") {
+                input_extension = "*";
+            } else {
+                return Ok(None);
+            }
+        }
+
+        Ok(self.extension_map.get_key_value(input_extension).map(|(key, _)| key.as_str()))
+    }
+}
+
+/// Walks inputs recursively, pairing every file with the directory tree it was found in
+fn collect_files(input_files: Vec<PathBuf>, tree: Option<PathBuf>, files: &mut Vec<(PathBuf, Option<PathBuf>)>) -> Result<(), anyhow::Error> {
+    for input_file in input_files {
+        if input_file.is_dir() {
+            let mut entries: Vec<PathBuf> = read_dir(&input_file)
+                .with_context(|| format!("Cound not read directory: '{}'", input_file.display()))?
+                .collect::<Result<Vec<DirEntry>, _>>()
+                .with_context(|| format!("Error while reading directory: '{}'", input_file.display()))?
+                .into_iter()
+                .map(|x| input_file.join(x.path()))
+                .collect();
+            // Sorted, so {{index}} is the same on every run
+            entries.sort();
+
+            collect_files(
+                entries,
+                if let Some(t) = &tree {
+                    Some(t.join(input_file.file_name().with_context(|| format!("could not read file_name: {}", input_file.display()))?))
+                } else {
+                    Some(input_file.file_name().with_context(|| format!("Could not read file_name: '{}'", input_file.display()))?.into())
+                },
+                files
+            )?;
+            continue;
+        }
+
+        files.push((input_file, tree.clone()));
+    }
+    Ok(())
+}
+
+/// Output was modified at the same time or after the input
+fn is_up_to_date(input_file: &Path, output_file: &Path) -> Result<bool, anyhow::Error> {
+    let input_modified = metadata(input_file)
+        .and_then(|x| x.modified())
+        .with_context(|| format!("Could not read modification time: '{}'", input_file.display()))?;
+    let output_modified = metadata(output_file)
+        .and_then(|x| x.modified())
+        .with_context(|| format!("Could not read modification time: '{}'", output_file.display()))?;
+
+    Ok(output_modified >= input_modified)
+}
@@ -1,15 +1,17 @@
 use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
 use crate::ffmpeg::{FFmpegError, FFmpegOptions};
 
-// FIXME: This work fine but requires use of unsafe.
-// Idealy I want theese to be members of OverallProgress 
-// however the with_key functions will need to hold a reference to the object 
-// which I cannot figure out how to do so yea. This is stupid maybe idk but it 
-// works and that's good to me. whatever.
-static mut N_SUCCEEDED: u64 = 0;
-static mut N_ERRORED: u64 = 0;
-static mut N_TOTAL: u64 = 0;
+/// Job counts shown by the overall bar, shared with the keys of its style
+#[derive(Debug, Default)]
+struct Counters {
+    succeeded: AtomicU64,
+    errored: AtomicU64,
+    total: AtomicU64,
+}
 
 #[derive(Debug)]
 pub struct FFmpegProgress<'a> {
@@ -32,7 +34,9 @@ impl<'a> FFmpegProgress<'a> {
             .progress_chars("##-");
 
         progress_bar.set_style(style);
-        progress_bar.set_message(format!("{}", options.input_file.file_name().unwrap().to_str().unwrap()));
+        progress_bar.set_message(options.input_file.file_name().unwrap().to_str().unwrap().to_owned());
+        // Keeps the spinner and elapsed time moving while ffmpeg prints nothing
+        progress_bar.enable_steady_tick(Duration::from_millis(100));
 
         Self {
             has_duration,
@@ -41,36 +45,26 @@ impl<'a> FFmpegProgress<'a> {
         }
     }
 
-    pub fn update(&self, metric_str: Option<&str>) {
+    /// `seconds_processed` is what the converter parsed from a line it printed, `None` only ticks the spinner
+    pub fn update(&self, seconds_processed: Option<u64>) {
         self.progress_bar.tick();
 
-        if !self.has_duration || metric_str.is_none() {
+        let Some(seconds_processed) = seconds_processed.filter(|_| self.has_duration) else {
             return;
-        }
-
-        let metric: &str;
-        let value: &str;
-
-        if let Some((m, v)) = metric_str.unwrap().trim().split_once('=') {
-            metric = m.trim();
-            value = v.trim();
-        } else { 
-            return; 
-        }
-
-        if metric.ne("out_time_ms") || value.is_empty() || value.parse::<u64>().is_err() { 
-            return; 
-        }
+        };
 
         let last_position = self.progress_bar.position();
 
-        let seconds_processed = value.parse::<u64>().unwrap() / 1_000_000;
-
         self.progress_bar.set_position(seconds_processed.min(self.progress_bar.length().expect("Length set in the constructor")));
 
         self.overall_progress.update(self.progress_bar.position().saturating_sub(last_position));
     }
 
+    /// Removes the bar without counting its remaining length as done
+    pub fn cancel(&self) {
+        self.progress_bar.finish_and_clear();
+    }
+
     pub fn finish(&self) {
         let last_position = self.progress_bar.position();
         self.progress_bar.set_position(self.progress_bar.length().expect("Length set in the constructor"));
@@ -83,25 +77,28 @@ impl<'a> FFmpegProgress<'a> {
 pub struct OverallProgress {
     pub manager: MultiProgress,
     pub progress_bar: ProgressBar,
-
+    counters: Arc<Counters>,
 }
 
 impl OverallProgress {
-    pub fn new(total_duration: u64, n_items: u64) -> Self {
-        let manager = MultiProgress::new();
+    pub fn new(manager: MultiProgress, total_duration: u64, n_items: u64) -> Self {
         let progress_bar = manager.add(ProgressBar::new(total_duration));
 
-        unsafe { 
-            N_TOTAL = n_items; 
-        }
+        let counters = Arc::new(Counters { total: AtomicU64::new(n_items), ..Counters::default() });
+        let key = |get: fn(&Counters) -> &AtomicU64| {
+            let counters = counters.clone();
+            move |_: &ProgressState, w: &mut dyn Write| {
+                let value = get(&counters).load(Ordering::Relaxed);
+                write!(w, "{:^1$}", value, value.to_string().len() + 2).unwrap()
+            }
+        };
 
         let style = ProgressStyle::with_template(
             "  [{bar:50.green}] {percent:>3}% (eta: {eta_precise}) [{err:.red}/{ok:.green}/{all}]")
             .unwrap()
-            // FIXME: I cannot figure out how to do this without shared state but it works so it's fine
-            .with_key("err", |_: &ProgressState, w: &mut dyn Write| write!(w, "{:^1$}", unsafe { N_ERRORED }, unsafe { N_ERRORED }.to_string().len() + 2).unwrap())
-            .with_key("ok", |_: &ProgressState, w: &mut dyn Write| write!(w, "{:^1$}", unsafe { N_SUCCEEDED }, unsafe { N_SUCCEEDED }.to_string().len() + 2).unwrap())
-            .with_key("all", |_: &ProgressState, w: &mut dyn Write| write!(w, "{:^1$}", unsafe { N_TOTAL }, unsafe { N_TOTAL }.to_string().len() + 2).unwrap())
+            .with_key("err", key(|x| &x.errored))
+            .with_key("ok", key(|x| &x.succeeded))
+            .with_key("all", key(|x| &x.total))
             .progress_chars("#> ");
 
         progress_bar.set_message(" 0 ");
@@ -111,6 +108,7 @@ impl OverallProgress {
         Self {
             manager,
             progress_bar,
+            counters,
         }
     }
 
@@ -118,15 +116,22 @@ impl OverallProgress {
         self.progress_bar.inc(increase);
     }
 
+    /// Counts a job that was not known when the progress was created
+    pub fn add_job(&self) {
+        self.counters.total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds the length of a job once its duration is probed
+    pub fn add_duration(&self, options: &FFmpegOptions) {
+        self.progress_bar.inc_length(options.duration.unwrap_or(1.0).floor() as u64);
+    }
+
     pub fn update_completed(&self, error: &Option<FFmpegError>) {
-        // FIXME: unsafe here too
-        unsafe {
-            if error.is_some() {
-                N_ERRORED += 1;
-            } else {
-                N_SUCCEEDED += 1;
-            }
-        }
+        let counter = match error {
+            Some(_) => &self.counters.errored,
+            None => &self.counters.succeeded,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn finish(&self) {
@@ -134,3 +139,37 @@ impl OverallProgress {
         self.manager.clear().unwrap();
     }
 }
+
+/// Number of probed inputs, shown while ffprobe runs
+#[derive(Debug)]
+pub struct ProbeProgress {
+    pub progress_bar: ProgressBar,
+}
+
+impl ProbeProgress {
+    pub fn new(manager: &MultiProgress, n_jobs: u64) -> Self {
+        let progress_bar = manager.insert(0, ProgressBar::new(n_jobs));
+
+        let style = ProgressStyle::with_template("{spinner:.cyan} Probing inputs [{pos}/{len}]")
+            .unwrap();
+
+        progress_bar.set_style(style);
+        progress_bar.enable_steady_tick(Duration::from_millis(100));
+
+        Self { progress_bar }
+    }
+
+    pub fn add_job(&self) {
+        self.progress_bar.inc_length(1);
+    }
+
+    /// Returns `true` once every added job is probed
+    pub fn update_probed(&self) -> bool {
+        self.progress_bar.inc(1);
+        self.progress_bar.position() >= self.progress_bar.length().expect("Length set in the constructor")
+    }
+
+    pub fn finish(&self) {
+        self.progress_bar.finish_and_clear();
+    }
+}
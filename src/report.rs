@@ -0,0 +1,106 @@
+use std::{fs::{metadata, File}, io::BufWriter, path::{Path, PathBuf}, time::Duration};
+use anyhow::Context;
+use serde::Serialize;
+use crate::ffmpeg::FFmpegProcessCompleted;
+use crate::media::MediaInfo;
+use crate::planner::Skipped;
+use crate::runner::RunOutcome;
+
+/// Machine readable summary of a run, written with '--report'
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub total_time: f64,
+    pub jobs: Vec<JobReport>,
+    pub skipped: Vec<SkippedReport>,
+    /// Inputs of jobs that were killed because the run was interrupted
+    pub cancelled: Vec<PathBuf>,
+    /// Inputs of jobs that were never started (e.g. because of '--fail-fast')
+    pub not_started: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobReport {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub rule: String,
+    /// 'success', 'warning' (succeeded but wrote to stderr) or 'failure'
+    pub status: &'static str,
+    /// Exit code of ffmpeg, `None` if it did not start or was killed by a signal
+    pub exit_status: Option<i32>,
+    /// Error message if ffmpeg could not be started
+    pub spawn_error: Option<String>,
+    /// Wall time in seconds
+    pub wall_time: f64,
+    pub input_size: Option<u64>,
+    pub output_size: Option<u64>,
+    /// Probed duration of the input in seconds
+    pub duration: Option<f64>,
+    /// What ffprobe found out about the input
+    pub media_info: Option<MediaInfo>,
+    pub stderr: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedReport {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub reason: String,
+}
+
+impl From<&FFmpegProcessCompleted> for JobReport {
+    fn from(completed: &FFmpegProcessCompleted) -> Self {
+        let options = &completed.options;
+        let (exit_status, spawn_error, stderr) = match &completed.output {
+            Ok(output) => (output.status.code(), None, String::from_utf8_lossy(&output.stderr).into_owned()),
+            Err(err) => (None, Some(err.to_string()), String::new()),
+        };
+
+        Self {
+            input: options.input_file.clone(),
+            output: options.output_file.clone(),
+            rule: options.rule.clone(),
+            status: completed.get_status().as_str(),
+            exit_status,
+            spawn_error,
+            wall_time: completed.wall_time.as_secs_f64(),
+            input_size: file_size(&options.input_file),
+            output_size: file_size(&options.output_file),
+            duration: options.duration,
+            media_info: options.media_info.clone(),
+            stderr,
+        }
+    }
+}
+
+impl From<&Skipped> for SkippedReport {
+    fn from(skipped: &Skipped) -> Self {
+        Self {
+            input: skipped.input_file.clone(),
+            output: skipped.output_file.clone(),
+            reason: skipped.reason.to_string(),
+        }
+    }
+}
+
+impl RunReport {
+    pub fn new(outcome: &RunOutcome, skipped: &[Skipped], total_time: Duration) -> Self {
+        Self {
+            total_time: total_time.as_secs_f64(),
+            jobs: outcome.completed.iter().map(JobReport::from).collect(),
+            skipped: skipped.iter().map(SkippedReport::from).collect(),
+            cancelled: outcome.cancelled.iter().map(|x| x.input_file.clone()).collect(),
+            not_started: outcome.not_started.iter().map(|x| x.input_file.clone()).collect(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let file = File::create(path)
+            .with_context(|| format!("Could not create report file: '{}'", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .with_context(|| format!("Could not write report file: '{}'", path.display()))
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    metadata(path).ok().map(|x| x.len())
+}
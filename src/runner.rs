@@ -0,0 +1,303 @@
+use std::{collections::{HashMap, VecDeque}, fs::create_dir_all, io::{BufRead, BufReader, Read}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver, RecvTimeoutError, Sender}, Arc, Mutex}, thread, time::Duration};
+use anyhow::Context;
+use indicatif::MultiProgress;
+use crate::ffmpeg::{kill_process_group, FFmpegProcessCompleted, FFmpegProcessStarted};
+use crate::planner::Job;
+use crate::progress::{FFmpegProgress, OverallProgress, ProbeProgress};
+
+/// How often the scheduler wakes up without any event to check for cancellation
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Everything the scheduler waits for, sent by per-process reader threads and the incoming jobs forwarder
+enum Event {
+    /// ffprobe finished for a job that is waiting to start
+    Probed(Job),
+    /// A line ffmpeg printed to stdout ('-progress -')
+    Progress(usize, String),
+    /// ffmpeg closed its stdout, with everything it printed to stderr
+    Exited(usize, Vec<u8>),
+    Incoming(Job),
+    /// No more jobs will be received
+    IncomingClosed,
+}
+
+struct FFmpegProcessWithProgress<'a> {
+    process: FFmpegProcessStarted,
+    progress: FFmpegProgress<'a>,
+}
+
+impl FFmpegProcessWithProgress<'_> {
+    fn pid(&self) -> Option<u32> {
+        self.process.child.as_ref().ok().map(|x| x.id())
+    }
+
+    pub fn finish(self, stderr: Vec<u8>) -> FFmpegProcessCompleted {
+        self.progress.finish();
+        self.process.finish_with_stderr(stderr)
+    }
+
+    pub fn cancel(self) -> Job {
+        self.progress.cancel();
+        self.process.cancel()
+    }
+}
+
+/// Runs planned jobs with a bounded number of concurrent ffmpeg processes
+#[derive(Debug)]
+pub struct Runner {
+    pub n_subprocesses: u32,
+    /// Max number of concurrent ffprobe processes probing inputs of jobs waiting to start
+    pub n_probes: u32,
+    /// Stop starting new jobs after the first failed one
+    pub fail_fast: bool,
+    /// When set, running ffmpeg processes are killed, their outputs removed and no new jobs are started
+    pub cancel: Arc<AtomicBool>,
+    /// Where progress bars are drawn, anything printed while running should go through it
+    pub progress: MultiProgress,
+    /// Jobs of running processes by process id, removed before the process is waited for (see [`kill_running`])
+    pub running: Arc<Mutex<HashMap<u32, Job>>>,
+}
+
+/// Jobs that ran (in order of completion), jobs that were killed and jobs that were never started
+#[derive(Default)]
+pub struct RunOutcome {
+    pub completed: Vec<FFmpegProcessCompleted>,
+    pub cancelled: Vec<Job>,
+    pub not_started: Vec<Job>,
+}
+
+impl RunOutcome {
+    pub fn n_failed(&self) -> usize {
+        self.completed.iter().filter(|x| x.get_error().is_some()).count()
+    }
+
+    pub fn n_succeeded(&self) -> usize {
+        self.completed.len() - self.n_failed()
+    }
+}
+
+impl Runner {
+    pub fn new(n_subprocesses: u32) -> Self {
+        Self { n_subprocesses, n_probes: n_subprocesses, fail_fast: false, cancel: Arc::new(AtomicBool::new(false)), progress: MultiProgress::new(), running: Arc::default() }
+    }
+
+    /// Creates output directories and runs every job
+    pub fn run(&self, jobs: Vec<Job>) -> Result<RunOutcome, anyhow::Error> {
+        create_hierarchy(&jobs)?;
+        Ok(run_ffmpeg_concurrent(self, jobs, None))
+    }
+
+    /// Like [`Runner::run`], but also runs jobs received from `incoming` (after the given ones)
+    /// until it is disconnected or the run is cancelled. Output directories of incoming jobs must already exist
+    pub fn run_with_incoming(&self, jobs: Vec<Job>, incoming: Receiver<Job>) -> Result<RunOutcome, anyhow::Error> {
+        create_hierarchy(&jobs)?;
+        Ok(run_ffmpeg_concurrent(self, jobs, Some(incoming)))
+    }
+}
+
+/// Kills the running processes of a [`Runner`] with everything they started and removes their temporary files.
+/// For exiting from a signal handler without waiting for the run to stop
+pub fn kill_running(running: &Mutex<HashMap<u32, Job>>) {
+    let mut running = running.lock().unwrap_or_else(|x| x.into_inner());
+    for (pid, job) in running.drain() {
+        kill_process_group(pid);
+        job.remove_tmp_files();
+    }
+}
+
+pub fn create_hierarchy(jobs: &[Job]) -> Result<(), anyhow::Error> {
+    for job in jobs {
+        create_dir_all(
+            job.output_file.parent().with_context(|| format!("could not get parent of file: '{}'", job.output_file.display()))?
+        ).with_context(|| format!("could not create directory hierarchy: '{}'", job.output_file.parent().unwrap().display()))?;
+
+        if let Some(tmp_dir) = &job.tmp_dir {
+            create_dir_all(tmp_dir).with_context(|| format!("could not create temporary directory: '{}'", tmp_dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Probes the input of every job that was not probed yet with its converter, up to `n_probes` at a time
+pub fn probe_jobs(jobs: &mut [Job], n_probes: u32) {
+    let unprobed: Vec<&mut Job> = jobs.iter_mut().filter(|x| x.media_info.is_none()).collect();
+    let progress = ProbeProgress::new(&MultiProgress::new(), unprobed.len() as u64);
+    let remaining = Mutex::new(unprobed.into_iter());
+    let next_job = || remaining.lock().unwrap().next();
+
+    thread::scope(|scope| {
+        for _ in 0..n_probes {
+            scope.spawn(|| {
+                while let Some(job) = next_job() {
+                    job.probe();
+                    progress.update_probed();
+                }
+            });
+        }
+    });
+    progress.finish();
+}
+
+/// Reads stdout and stderr of a started ffmpeg in their own threads, so a silent process never holds up the others.
+/// Returns `false` if ffmpeg did not start
+fn spawn_readers(id: usize, process: &mut FFmpegProcessStarted, events: &Sender<Event>) -> bool {
+    let Ok(child) = &mut process.child else {
+        return false;
+    };
+    let stdout = child.stdout.take().expect("Spawned with piped stdout");
+    let mut stderr = child.stderr.take().expect("Spawned with piped stderr");
+    let events = events.clone();
+
+    thread::spawn(move || {
+        // stderr is drained concurrently, a full stderr pipe would block ffmpeg and with it stdout
+        let stderr_reader = thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = stderr.read_to_end(&mut buffer);
+            buffer
+        });
+
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if events.send(Event::Progress(id, line)).is_err() {
+                break;
+            }
+        }
+
+        let _ = events.send(Event::Exited(id, stderr_reader.join().unwrap_or_default()));
+    });
+    true
+}
+
+/// Probes the input of a job in its own thread, the job is sent back once done
+fn spawn_probe(mut job: Job, events: &Sender<Event>) {
+    let events = events.clone();
+    thread::spawn(move || {
+        job.probe();
+        let _ = events.send(Event::Probed(job));
+    });
+}
+
+fn run_ffmpeg_concurrent(runner: &Runner, jobs: Vec<Job>, incoming: Option<Receiver<Job>>) -> RunOutcome {
+    let overall_progress = OverallProgress::new(runner.progress.clone(), 0, jobs.len() as u64);
+    let mut probe_progress = (!jobs.is_empty()).then(|| ProbeProgress::new(&overall_progress.manager, jobs.len() as u64));
+
+    let (event_sender, events) = channel();
+    let mut incoming_open = incoming.is_some();
+    if let Some(incoming) = incoming {
+        let event_sender = event_sender.clone();
+        thread::spawn(move || {
+            for job in incoming {
+                if event_sender.send(Event::Incoming(job)).is_err() {
+                    return;
+                }
+            }
+            let _ = event_sender.send(Event::IncomingClosed);
+        });
+    }
+
+    // Jobs are probed before they start so their progress bar has a length, conversion starts with the first probed job
+    let mut waiting_for_probe: VecDeque<Job> = jobs.into();
+    let mut probed_jobs: VecDeque<Job> = VecDeque::new();
+    let mut n_probing = 0;
+
+    let mut running_processes: HashMap<usize, FFmpegProcessWithProgress> = HashMap::new();
+    let mut completed_processes: Vec<FFmpegProcessCompleted> = Vec::new();
+    let mut cancelled: Vec<Job> = Vec::new();
+    let mut next_id = 0;
+    let mut any_failed = false;
+
+    while !runner.cancel.load(Ordering::SeqCst) {
+        while !(runner.fail_fast && any_failed) && (n_probing as u32) < runner.n_probes {
+            let Some(job) = waiting_for_probe.pop_front() else { break };
+            if job.media_info.is_some() {
+                // probed while planning
+                let _ = event_sender.send(Event::Probed(job));
+            } else {
+                spawn_probe(job, &event_sender);
+            }
+            n_probing += 1;
+        }
+
+        while !(runner.fail_fast && any_failed) && (running_processes.len() as u32) < runner.n_subprocesses {
+            let Some(job) = probed_jobs.pop_front() else { break };
+
+            let progress = FFmpegProgress::new(&overall_progress, &job);
+            let mut process = job.start();
+
+            if spawn_readers(next_id, &mut process, &event_sender) {
+                let process = FFmpegProcessWithProgress { process, progress };
+                if let Some(pid) = process.pid() {
+                    runner.running.lock().unwrap().insert(pid, process.process.options.clone());
+                }
+                running_processes.insert(next_id, process);
+                next_id += 1;
+            } else {
+                progress.finish();
+                let completed_process = process.finish();
+                any_failed |= completed_process.get_error().is_some();
+                overall_progress.update_completed(&completed_process.get_error());
+                completed_processes.push(completed_process);
+            }
+        }
+
+        let nothing_left = waiting_for_probe.is_empty() && probed_jobs.is_empty() && !incoming_open;
+        if running_processes.is_empty() && n_probing == 0 && ((runner.fail_fast && any_failed) || nothing_left) {
+            break;
+        }
+
+        match events.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(Event::Progress(id, line)) => {
+                if let Some(process) = running_processes.get(&id) {
+                    process.progress.update(process.process.options.converter.parse_progress(&line));
+                }
+            },
+            Ok(Event::Exited(id, stderr)) => {
+                let Some(process) = running_processes.remove(&id) else { continue };
+                if let Some(pid) = process.pid() {
+                    runner.running.lock().unwrap().remove(&pid);
+                }
+                if runner.cancel.load(Ordering::SeqCst) {
+                    // Exited because of the interrupt, not because the conversion failed
+                    cancelled.push(process.cancel());
+                } else {
+                    let completed_process = process.finish(stderr);
+                    any_failed |= completed_process.get_error().is_some();
+                    overall_progress.update_completed(&completed_process.get_error());
+                    completed_processes.push(completed_process);
+                }
+            },
+            Ok(Event::Probed(job)) => {
+                n_probing -= 1;
+                overall_progress.add_duration(&job);
+                if probe_progress.as_ref().is_some_and(|x| x.update_probed()) {
+                    probe_progress.take().unwrap().finish();
+                }
+                probed_jobs.push_back(job);
+            },
+            Ok(Event::Incoming(job)) => {
+                overall_progress.add_job();
+                probe_progress.get_or_insert_with(|| ProbeProgress::new(&overall_progress.manager, 0)).add_job();
+                waiting_for_probe.push_back(job);
+            },
+            Ok(Event::IncomingClosed) => incoming_open = false,
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => unreachable!("The scheduler holds a sender"),
+        }
+    }
+
+    for process in running_processes.into_values() {
+        if let Some(pid) = process.pid() {
+            runner.running.lock().unwrap().remove(&pid);
+        }
+        cancelled.push(process.cancel());
+    }
+
+    if let Some(probe_progress) = probe_progress {
+        probe_progress.finish();
+    }
+    overall_progress.finish();
+    RunOutcome {
+        completed: completed_processes,
+        cancelled,
+        not_started: probed_jobs.into_iter().chain(waiting_for_probe).collect(),
+    }
+}
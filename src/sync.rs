@@ -0,0 +1,96 @@
+use std::{collections::HashSet, fs::{read_dir, remove_dir, remove_file}, path::{Path, PathBuf}};
+use anyhow::Context;
+use crate::parser::{ExtensionMap, OutputPattern};
+use crate::planner::{Job, Plan, Planner, SkipReason, Skipped};
+use crate::template::Template;
+
+/// Planner that mirrors the directory tree of a source directory into `mirror`,
+/// skipping outputs that are newer than their source
+pub fn sync_planner(mirror: &Path, extension_map: ExtensionMap) -> Planner {
+    Planner {
+        update: true,
+        ..Planner::new(
+            OutputPattern::new(PathBuf::from(Template::escape(&mirror.to_string_lossy())).join(OutputPattern::TREE).join(OutputPattern::FILE))
+                .expect("Escaped directory with known placeholders is a valid pattern"),
+            extension_map,
+        )
+    }
+}
+
+/// Jobs needed to bring a mirror up to date with its source, and the mirror files that have no source anymore
+#[derive(Debug)]
+pub struct SyncPlan {
+    pub plan: Plan,
+    /// Files in the mirror that are not the output of any source file
+    pub orphans: Vec<PathBuf>,
+}
+
+impl SyncPlan {
+    /// Plans the contents of `source` (not the directory itself) with a planner from [`sync_planner`]
+    pub fn new(planner: &Planner, source: &Path, mirror: &Path) -> Result<Self, anyhow::Error> {
+        let mut entries = read_dir(source)
+            .with_context(|| format!("Could not read directory: '{}'", source.display()))?
+            .map(|x| x.map(|x| x.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .with_context(|| format!("Error while reading directory: '{}'", source.display()))?;
+        entries.sort();
+
+        let plan = planner.plan(entries)?;
+
+        let expected: HashSet<&Path> = plan.jobs.iter().map(|x| x.output_file.as_path())
+            .chain(plan.skipped.iter().filter_map(|x| x.output_file.as_deref()))
+            .collect();
+
+        let mut orphans = Vec::new();
+        if mirror.is_dir() {
+            find_orphans(mirror, &expected, &mut orphans)?;
+        }
+        orphans.sort();
+
+        Ok(Self { plan, orphans })
+    }
+
+    /// Jobs whose output does not exist yet
+    pub fn added(&self) -> impl Iterator<Item = &Job> {
+        self.plan.jobs.iter().filter(|x| !x.output_file.exists())
+    }
+
+    /// Jobs whose output exists but is older than the source
+    pub fn changed(&self) -> impl Iterator<Item = &Job> {
+        self.plan.jobs.iter().filter(|x| x.output_file.exists())
+    }
+
+    /// Sources whose output is up to date
+    pub fn unchanged(&self) -> impl Iterator<Item = &Skipped> {
+        self.plan.skipped.iter().filter(|x| x.reason == SkipReason::UpToDate)
+    }
+
+    /// Deletes every orphan and the directories left empty by it, up to (not including) `mirror`
+    pub fn remove_orphans(&self, mirror: &Path) -> Result<(), anyhow::Error> {
+        for orphan in self.orphans.iter() {
+            remove_file(orphan).with_context(|| format!("Could not remove file: '{}'", orphan.display()))?;
+
+            let mut parent = orphan.parent();
+            while let Some(dir) = parent {
+                if dir == mirror || !dir.starts_with(mirror) || remove_dir(dir).is_err() {
+                    break;
+                }
+                parent = dir.parent();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn find_orphans(dir: &Path, expected: &HashSet<&Path>, orphans: &mut Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    for entry in read_dir(dir).with_context(|| format!("Could not read directory: '{}'", dir.display()))? {
+        let path = entry.with_context(|| format!("Error while reading directory: '{}'", dir.display()))?.path();
+
+        if path.is_dir() {
+            find_orphans(&path, expected, orphans)?;
+        } else if !expected.contains(path.as_path()) {
+            orphans.push(path);
+        }
+    }
+    Ok(())
+}
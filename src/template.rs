@@ -0,0 +1,240 @@
+use anyhow::Context;
+
+/// A text with `{{placeholders}}`, e.g. `{{tag:track:02}} {{tag:title?Untitled|lower|trunc:40}}`.
+/// Placeholder names are not interpreted here, values are supplied when rendering.
+/// Literal braces are written twice: `{{{{` and `}}}}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// `{{NAME:ARG:ARG?FALLBACK|FILTER|FILTER:ARG}}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    /// Everything between the curly brackets, for error messages
+    pub source: String,
+    pub name: String,
+    /// Colon separated arguments after the name, e.g. `["artist"]` for `{{tag:artist}}`
+    pub args: Vec<String>,
+    /// Text after a question mark, used when the value is missing
+    pub fallback: Option<String>,
+    /// Applied in order after the value is resolved
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `lower`
+    Lower,
+    /// `upper`
+    Upper,
+    /// `slug` - lowercase, runs of anything but letters and digits become a single '-'
+    Slug,
+    /// `replace:FROM,TO`
+    Replace(String, String),
+    /// `trunc:N` - keeps the first N characters
+    Trunc(usize),
+    /// `default:TEXT` - used if the value is empty
+    Default(String),
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if let Some(escaped) = ["{{{{", "}}}}"].into_iter().find(|x| rest.starts_with(x)) {
+                literal.push_str(&escaped[..2]);
+                rest = &rest[4..];
+            } else if rest.starts_with("}}") {
+                anyhow::bail!("Unbalanced '}}}}' in: '{text}', write '}}}}}}}}' for literal braces");
+            } else if rest.starts_with("{{") {
+                let source = match rest[2..].find("}}") {
+                    Some(end) if !rest[2..end + 2].contains("{{") => &rest[2..end + 2],
+                    _ => anyhow::bail!("Unclosed '{{{{' in: '{text}', write '{{{{{{{{' for literal braces"),
+                };
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Placeholder(Placeholder::parse(source)?));
+                rest = &rest[source.len() + 4..];
+            } else {
+                literal.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Escapes braces so `text` is used literally
+    pub fn escape(text: &str) -> String {
+        text.replace("{{", "{{{{").replace("}}", "}}}}")
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = &Placeholder> {
+        self.segments.iter().filter_map(|x| match x {
+            Segment::Placeholder(p) => Some(p),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Replaces every placeholder with the value returned by `value`, `None` meaning the value is missing.
+    /// A missing value becomes the placeholder's fallback, or `missing` if there is none and filters leave it empty
+    pub fn render(
+        &self,
+        missing: &str,
+        mut value: impl FnMut(&Placeholder) -> Result<Option<String>, anyhow::Error>,
+    ) -> Result<String, anyhow::Error> {
+        let mut rendered = String::new();
+
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Placeholder(placeholder) => {
+                    let resolved = value(placeholder)?;
+                    let is_missing = resolved.is_none() && placeholder.fallback.is_none();
+                    let text = placeholder.apply_filters(resolved.or_else(|| placeholder.fallback.clone()).unwrap_or_default());
+
+                    rendered.push_str(if is_missing && text.is_empty() { missing } else { &text });
+                },
+            }
+        }
+
+        Ok(rendered)
+    }
+}
+
+impl Placeholder {
+    fn parse(source: &str) -> Result<Self, anyhow::Error> {
+        let mut parts = source.split('|');
+        let head = parts.next().expect("Split returns at least one part");
+
+        let (head, fallback) = match head.split_once('?') {
+            Some((head, fallback)) => (head, Some(fallback.to_owned())),
+            None => (head, None),
+        };
+        let mut args = head.split(':').map(|x| x.trim().to_owned());
+        let name = args.next().expect("Split returns at least one part");
+
+        let filters = parts
+            .map(|x| Filter::parse(x).with_context(|| format!("Invalid filter in placeholder: '{{{{{source}}}}}'")))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            source: source.to_owned(),
+            name,
+            args: args.collect(),
+            fallback,
+            filters,
+        })
+    }
+
+    fn apply_filters(&self, value: String) -> String {
+        self.filters.iter().fold(value, |value, filter| filter.apply(value))
+    }
+}
+
+impl Filter {
+    pub const NAMES: [&'static str; 6] = ["lower", "upper", "slug", "replace", "trunc", "default"];
+
+    fn parse(filter: &str) -> Result<Self, anyhow::Error> {
+        let (name, arg) = match filter.split_once(':') {
+            Some((name, arg)) => (name.trim(), Some(arg)),
+            None => (filter.trim(), None),
+        };
+        let required_arg = || arg.with_context(|| format!("Filter '{name}' needs an argument, e.g. '{name}:{}'", match name {
+            "replace" => " ,_",
+            "trunc" => "40",
+            _ => "TEXT",
+        }));
+
+        Ok(match name {
+            "lower" => Self::Lower,
+            "upper" => Self::Upper,
+            "slug" => Self::Slug,
+            "replace" => {
+                let (from, to) = required_arg()?.split_once(',')
+                    .with_context(|| format!("Filter 'replace' needs two comma separated arguments: '{filter}'"))?;
+                Self::Replace(from.to_owned(), to.to_owned())
+            },
+            "trunc" => Self::Trunc(required_arg()?.trim().parse()
+                .with_context(|| format!("Filter 'trunc' needs a number of characters: '{filter}'"))?),
+            "default" => Self::Default(required_arg()?.to_owned()),
+            _ => match nearest(name, &Self::NAMES) {
+                Some(nearest) => anyhow::bail!("Unknown filter '{name}', did you mean '{nearest}'?"),
+                None => anyhow::bail!("Unknown filter '{name}', expected one of: {}", Self::NAMES.join(", ")),
+            },
+        })
+    }
+
+    pub fn apply(&self, value: String) -> String {
+        match self {
+            Self::Lower => value.to_lowercase(),
+            Self::Upper => value.to_uppercase(),
+            Self::Slug => slugify(&value),
+            Self::Replace(from, to) if !from.is_empty() => value.replace(from, to),
+            Self::Replace(..) => value,
+            Self::Trunc(n) => value.chars().take(*n).collect(),
+            Self::Default(default) if value.is_empty() => default.clone(),
+            Self::Default(_) => value,
+        }
+    }
+}
+
+/// 'My Song (Live)' -> 'my-song-live'
+fn slugify(value: &str) -> String {
+    let mut slug = String::new();
+
+    for c in value.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+/// The candidate with the smallest edit distance to `name`, if it is close enough to be a typo
+pub fn nearest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|x| (edit_distance(name, x), *x))
+        .filter(|(distance, x)| *distance <= 2.max(x.chars().count() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| x)
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current.push((previous[j] + usize::from(ca != *cb)).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,151 @@
+use std::{collections::HashMap, fs::{metadata, read_dir}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver, RecvTimeoutError, Sender}, Arc}, thread, time::{Duration, Instant, SystemTime}};
+use anyhow::Context;
+use indicatif::MultiProgress;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use crate::planner::{Job, Plan, Planner};
+use crate::runner::create_hierarchy;
+
+/// How long a file must stop changing before it is converted
+pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// How often pending files are checked and cancellation is noticed
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Plans jobs for files that are created or modified inside input directories
+#[derive(Debug)]
+pub struct DirWatcher {
+    pub planner: Planner,
+    /// Input directories, watched recursively
+    pub roots: Vec<PathBuf>,
+    /// How long size and modification time of a file must stay the same before it is planned
+    pub settle_time: Duration,
+    /// Plan made before watching, so its outputs are not taken for new inputs and {{index}} continues after it
+    pub planned: Plan,
+}
+
+/// A file that changed recently and is waiting to stop growing
+struct Pending {
+    len: u64,
+    modified: Option<SystemTime>,
+    since: Instant,
+}
+
+impl Pending {
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = metadata(path).ok()?;
+        Some(Self { len: metadata.len(), modified: metadata.modified().ok(), since: Instant::now() })
+    }
+}
+
+impl DirWatcher {
+    pub fn new(planner: Planner, roots: Vec<PathBuf>) -> Self {
+        Self { planner, roots, settle_time: DEFAULT_SETTLE_TIME, planned: Plan::default() }
+    }
+
+    /// Starts watching in a background thread. Jobs are sent to the returned receiver (their output
+    /// directories already created) until `cancel` is set. Errors are printed above the bars of `progress`
+    pub fn spawn(self, cancel: Arc<AtomicBool>, progress: MultiProgress) -> Result<Receiver<Job>, anyhow::Error> {
+        let (event_sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(event_sender).context("Could not start watching files")?;
+        for root in self.roots.iter() {
+            watcher.watch(root, RecursiveMode::Recursive)
+                .with_context(|| format!("Could not watch directory: '{}'", root.display()))?;
+        }
+
+        let (job_sender, jobs) = channel();
+        thread::spawn(move || {
+            let _watcher = watcher;
+            self.watch(events, job_sender, &cancel, &progress);
+        });
+        Ok(jobs)
+    }
+
+    fn watch(mut self, events: Receiver<notify::Result<Event>>, jobs: Sender<Job>, cancel: &AtomicBool, progress: &MultiProgress) {
+        let mut plan = std::mem::take(&mut self.planned);
+        let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+
+        while !cancel.load(Ordering::SeqCst) {
+            match events.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) => if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        self.add_pending(path, &plan, &mut pending);
+                    }
+                },
+                Ok(Err(err)) => progress.suspend(|| eprintln!("Error while watching files: {err}")),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            for path in self.take_settled(&mut pending) {
+                // A modified input is converted again, its old jobs would otherwise collide with the new ones
+                plan.jobs.retain(|x| x.input_file != path);
+                let n_planned = plan.jobs.len();
+                if let Err(err) = self.plan(path, &mut plan) {
+                    progress.suspend(|| eprintln!("Error: {err:?}"));
+                    continue;
+                }
+
+                for job in plan.jobs[n_planned..].iter() {
+                    if jobs.send(job.clone()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Files in new directories may have been created before the directory itself was watched
+    fn add_pending(&self, path: PathBuf, plan: &Plan, pending: &mut HashMap<PathBuf, Pending>) {
+        if path.is_dir() {
+            for entry in read_dir(&path).into_iter().flatten().flatten() {
+                self.add_pending(entry.path(), plan, pending);
+            }
+        } else if self.is_new_input(&path, plan) {
+            if let Some(state) = Pending::read(&path) {
+                pending.insert(path, state);
+            }
+        }
+    }
+
+    /// Removes and returns the pending files that did not change for `settle_time`
+    fn take_settled(&self, pending: &mut HashMap<PathBuf, Pending>) -> Vec<PathBuf> {
+        let mut settled = Vec::new();
+
+        pending.retain(|path, state| {
+            let Some(current) = Pending::read(path) else {
+                return false; // removed or renamed away
+            };
+            if current.len != state.len || current.modified != state.modified {
+                *state = current;
+                return true;
+            }
+            if state.since.elapsed() < self.settle_time {
+                return true;
+            }
+            settled.push(path.clone());
+            false
+        });
+
+        settled.sort();
+        settled
+    }
+
+    fn plan(&self, path: PathBuf, plan: &mut Plan) -> Result<(), anyhow::Error> {
+        let root = self.roots
+            .iter()
+            .filter(|x| path.starts_with(x))
+            .max_by_key(|x| x.components().count())
+            .with_context(|| format!("File is not inside a watched directory: '{}'", path.display()))?;
+
+        let n_planned = plan.jobs.len();
+        self.planner.plan_in(path, root, plan)?;
+        create_hierarchy(&plan.jobs[n_planned..])
+    }
+
+    /// Files written by lconvert itself (temporary files and outputs) are not inputs
+    fn is_new_input(&self, path: &Path, plan: &Plan) -> bool {
+        path.is_file()
+            && !path.file_name().is_some_and(|x| x.to_string_lossy().contains(".lconvert-tmp"))
+            && !plan.jobs.iter().any(|x| x.output_file == path)
+    }
+}
@@ -4,6 +4,8 @@ use assert_fs::prelude::*;
 use std::fs::File;
 use std::io::Read;
 use std::process::Command;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
 
 static BIN_NAME: &str = "lconvert";
@@ -121,7 +123,7 @@ fn override_file() -> Result<(), Box<dyn std::error::Error>> {
     Command::cargo_bin(BIN_NAME)?
         .args(["-o", output_dir.to_str().unwrap(), "-m", "mp3=wav", input_file.to_str().unwrap()])
         .assert()
-        .success()
+        .code(4)
         .stderr(predicate::str::contains("already exists"));
 
     assert_eq!(expected_hash, hash!(&output_file));
@@ -302,9 +304,9 @@ fn output_pattern_with_no_templates() -> Result<(), Box<dyn std::error::Error>>
     input_file_2.write_file(get_test_file!(TEST_FILE_MP3))?;
     input_file_3.write_file(get_test_file!(TEST_FILE_OGG))?;
 
-    let _ = correct_dir.child("dir").child(input_dir.path()).child("test").child("input1.wav").touch();
-    let _ = correct_dir.child("dir").child(input_dir.path()).child("test").child("input2.wav").touch();
-    let _ = correct_dir.child("dir").child(input_dir.path()).child("test").child("input3.wav").touch();
+    let _ = correct_dir.child("dir").child(input_dir.file_name().unwrap()).child("test").child("input1.wav").touch();
+    let _ = correct_dir.child("dir").child(input_dir.file_name().unwrap()).child("test").child("input2.wav").touch();
+    let _ = correct_dir.child("dir").child(input_dir.file_name().unwrap()).child("test").child("input3.wav").touch();
 
     Command::cargo_bin(BIN_NAME)?
         .current_dir(&output_dir)
@@ -312,8 +314,8 @@ fn output_pattern_with_no_templates() -> Result<(), Box<dyn std::error::Error>>
         .assert()
         .success();
 
-    let test_output = read_dir!(output_dir.child("dir").child(input_dir.path()).child("test"));
-    let test_correct = read_dir!(correct_dir.child("dir").child(input_dir.path()).child("test"));
+    let test_output = read_dir!(output_dir.child("dir").child(input_dir.file_name().unwrap()).child("test"));
+    let test_correct = read_dir!(correct_dir.child("dir").child(input_dir.file_name().unwrap()).child("test"));
     assert_eq!(dbg!(test_output), dbg!(test_correct));
 
     Ok(())
@@ -332,9 +334,9 @@ fn output_pattern_default() -> Result<(), Box<dyn std::error::Error>> {
     input_file_2.write_file(get_test_file!(TEST_FILE_MP3))?;
     input_file_3.write_file(get_test_file!(TEST_FILE_OGG))?;
 
-    let _ = correct_dir.child("lconvert_output").child(output_dir.path()).child("test").child("input1.wav").touch()?;
-    let _ = correct_dir.child("lconvert_output").child(output_dir.path()).child("test").child("input2.wav").touch()?;
-    let _ = correct_dir.child("lconvert_output").child(output_dir.path()).child("test").child("input3.wav").touch()?;
+    correct_dir.child("lconvert_output").child(output_dir.path()).child("test").child("input1.wav").touch()?;
+    correct_dir.child("lconvert_output").child(output_dir.path()).child("test").child("input2.wav").touch()?;
+    correct_dir.child("lconvert_output").child(output_dir.path()).child("test").child("input3.wav").touch()?;
 
     Command::cargo_bin(BIN_NAME)?
         .current_dir(&output_dir)
@@ -363,9 +365,9 @@ fn output_pattern_unique() -> Result<(), Box<dyn std::error::Error>> {
     // test directory does not exist
     let output_dir = assert_fs::TempDir::new()?;
     let correct_dir = assert_fs::TempDir::new()?;
-    let _ = correct_dir.child("test").child("wav.wav").touch()?;
-    let _ = correct_dir.child("test").child("wav_1.wav").touch()?;
-    let _ = correct_dir.child("test").child("wav_2.wav").touch()?;
+    correct_dir.child("test").child("wav.wav").touch()?;
+    correct_dir.child("test").child("wav_1.wav").touch()?;
+    correct_dir.child("test").child("wav_2.wav").touch()?;
 
     Command::cargo_bin(BIN_NAME)?
         .current_dir(&output_dir)
@@ -379,12 +381,12 @@ fn output_pattern_unique() -> Result<(), Box<dyn std::error::Error>> {
 
     // test directory does exist
     let output_dir = assert_fs::TempDir::new()?;
-    let _ = output_dir.child("test").create_dir_all()?;
+    output_dir.child("test").create_dir_all()?;
 
     let correct_dir = assert_fs::TempDir::new()?;
-    let _ = correct_dir.child("test").child("wav.wav").touch()?;
-    let _ = correct_dir.child("test").child("wav_1.wav").touch()?;
-    let _ = correct_dir.child("test").child("wav_2.wav").touch()?;
+    correct_dir.child("test").child("wav.wav").touch()?;
+    correct_dir.child("test").child("wav_1.wav").touch()?;
+    correct_dir.child("test").child("wav_2.wav").touch()?;
 
     Command::cargo_bin(BIN_NAME)?
         .current_dir(&output_dir)
@@ -400,3 +402,831 @@ fn output_pattern_unique() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 // TODO: Add more tests
+
+#[test]
+fn dry_run() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("notes.txt").touch()?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("out").to_string_lossy(), "-m", "mp3=wav", "--dry-run", &input_dir.to_string_lossy(), "--", "-ab", "64KB"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mp3=wav"))
+        .stdout(predicate::str::contains("input1.wav"))
+        .stdout(predicate::str::contains("-ab 64KB"))
+        .stdout(predicate::str::contains("notes.txt"))
+        .stdout(predicate::str::contains("1 planned, 1 skipped"));
+
+    output_dir.child("out").assert(predicate::path::missing());
+
+    Ok(())
+}
+
+#[test]
+fn json_report() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+    let report_file = output_dir.child("report.json");
+
+    let input_file = input_dir.child("input1.mp3");
+    input_file.write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("notes.txt").touch()?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("out").to_string_lossy(), "-m", "mp3=wav", "--report", &report_file.to_string_lossy(), &input_dir.to_string_lossy()])
+        .assert()
+        .success();
+
+    let report: serde_json::Value = serde_json::from_reader(File::open(&report_file)?)?;
+
+    let jobs = report["jobs"].as_array().unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0]["input"], input_file.to_string_lossy().as_ref());
+    assert_eq!(jobs[0]["output"], output_dir.child("out").child(input_dir.file_name().unwrap()).child("input1.wav").to_string_lossy().as_ref());
+    assert_eq!(jobs[0]["rule"], "mp3=wav");
+    assert_eq!(jobs[0]["status"], "success");
+    assert_eq!(jobs[0]["exit_status"], 0);
+    assert!(jobs[0]["output_size"].as_u64().unwrap() > 0);
+    assert!(jobs[0]["wall_time"].is_f64());
+
+    let skipped = report["skipped"].as_array().unwrap();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0]["input"], input_dir.child("notes.txt").to_string_lossy().as_ref());
+
+    Ok(())
+}
+
+#[test]
+fn exit_codes_and_fail_fast() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("input2.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    output_dir.child("input1.wav").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    let pattern = output_dir.child("{{stem}}").to_string_lossy().into_owned();
+
+    // one of two outputs already exists
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern, "-m", "mp3=wav", "--dry-run", &input_dir.to_string_lossy()])
+        .assert()
+        .code(0);
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern, "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(3);
+
+    // both outputs exist, the first failure stops the second job
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern, "-m", "mp3=wav", "-n", "1", "--fail-fast", &input_dir.to_string_lossy()])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("1/1 files finished with errors!"))
+        .stderr(predicate::str::contains("1 files were not converted because of '--fail-fast'"));
+
+    Ok(())
+}
+
+#[test]
+fn failure_decided_by_exit_status() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+    let report_file = output_dir.child("report.json");
+
+    // not a valid media file, ffmpeg exits with an error
+    input_dir.child("broken.mp3").touch()?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", "--report", &report_file.to_string_lossy(), &input_dir.to_string_lossy()])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Error while trying to process input file"))
+        .stderr(predicate::str::contains("1/1 files finished with errors!"));
+
+    let report: serde_json::Value = serde_json::from_reader(File::open(&report_file)?)?;
+    assert_eq!(report["jobs"][0]["status"], "failure");
+    assert_ne!(report["jobs"][0]["exit_status"], 0);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_removes_partial_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("input2.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    // 'arealtime' slows ffmpeg down to playback speed so the run is still going when interrupted
+    let child = Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy(), "--", "-af", "arealtime"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    // Like Ctrl+C in a terminal, the whole process group (lconvert and every ffmpeg) gets the signal
+    Command::new("kill").args(["-INT", "--", &format!("-{}", child.id())]).assert().success();
+
+    let output = child.wait_with_output()?;
+
+    assert_eq!(output.status.code(), Some(130));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("0 files converted, 2 cancelled"));
+    assert_eq!(read_dir!(output_dir), Vec::<PathBuf>::new());
+
+    Ok(())
+}
+
+#[test]
+fn atomic_output_writes() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+    let tmp_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("broken.mp3").touch()?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", "--dry-run", &input_dir.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"\.input1\.\d+\.lconvert-tmp\.wav")?);
+
+    // failed conversion leaves nothing behind, successful one is moved into place
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(3);
+
+    assert_eq!(read_dir!(output_dir), vec![PathBuf::from("input1.wav")]);
+
+    // same with a separate directory for temporary files
+    let output_dir = assert_fs::TempDir::new()?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", "--tmp-dir", &tmp_dir.to_string_lossy(), &input_dir.to_string_lossy()])
+        .assert()
+        .code(3);
+
+    assert_eq!(read_dir!(output_dir), vec![PathBuf::from("input1.wav")]);
+    assert_eq!(read_dir!(tmp_dir), Vec::<PathBuf>::new());
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn interrupt_kills_processes_started_by_converters() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+    let script_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("a.txt").write_str("some text")?;
+    // the wrapper leaves the work to a process of its own, like 'sh -c' or soffice
+    script_dir.child("slow.sh").write_str("sleep 30 &\necho $! > \"$1\"\nwait\n")?;
+    let pid_file = script_dir.child("sleep.pid");
+    let command = format!("sh {} {} {{{{input}}}} {{{{output}}}}", script_dir.child("slow.sh").display(), pid_file.display());
+    let is_running = |pid: &str| std::fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|x| !x.contains(") Z "));
+
+    // the first interrupt cancels the run, a second one exits right away
+    for n_signals in [1, 2] {
+        let child = Command::cargo_bin(BIN_NAME)?
+            .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "txt=md@cmd", &input_dir.to_string_lossy()])
+            .args(["--cmd", &command])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+
+        std::thread::sleep(std::time::Duration::from_millis(1000));
+        for _ in 0..n_signals {
+            Command::new("kill").args(["-INT", "--", &format!("-{}", child.id())]).assert().success();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let output = child.wait_with_output()?;
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        assert_eq!(output.status.code(), Some(130));
+        assert!(!is_running(std::fs::read_to_string(pid_file.path())?.trim()));
+        assert_eq!(read_dir!(output_dir), Vec::<PathBuf>::new());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn same_output_twice() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("a.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("a.ogg").write_file(get_test_file!(TEST_FILE_OGG))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav,ogg=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("would both be converted to"));
+
+    assert_eq!(read_dir!(output_dir), Vec::<PathBuf>::new());
+
+    Ok(())
+}
+
+#[test]
+fn skip_existing_and_update() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+    let pattern = output_dir.child("{{stem}}").to_string_lossy().into_owned();
+
+    let input_file_1 = input_dir.child("input1.mp3");
+    let input_file_2 = input_dir.child("input2.mp3");
+    input_file_1.write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_file_2.write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    let output_file_1 = output_dir.child("input1.wav");
+    output_file_1.write_str("not converted")?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern, "-m", "mp3=wav", "--skip-existing", &input_dir.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total files      :  1"))
+        .stdout(predicate::str::contains("Skipped files    :  1"));
+
+    output_file_1.assert("not converted");
+    output_dir.child("input2.wav").assert(predicate::path::exists());
+
+    // both outputs are newer than the inputs
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern, "-m", "mp3=wav", "--update", &input_dir.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total files      :  0"));
+
+    // input changed after its output was written
+    let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    File::options().write(true).open(&output_file_1)?.set_modified(an_hour_ago)?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern, "-m", "mp3=wav", "--update", &input_dir.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total files      :  1"));
+
+    assert_ne!(std::fs::read(&output_file_1)?, b"not converted");
+
+    Ok(())
+}
+
+#[test]
+fn sync_mirror() -> Result<(), Box<dyn std::error::Error>> {
+    let source_dir = assert_fs::TempDir::new()?;
+    let mirror_dir = assert_fs::TempDir::new()?;
+
+    source_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    source_dir.child("a").child("input2.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    mirror_dir.child("b").child("orphan.wav").touch()?;
+
+    // orphans are kept when not confirmed
+    Command::cargo_bin(BIN_NAME)?
+        .args(["sync", "-m", "mp3=wav", &source_dir.to_string_lossy(), "-o", &mirror_dir.to_string_lossy()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("+ input1.wav"))
+        .stdout(predicate::str::contains("- b/orphan.wav"))
+        .stdout(predicate::str::contains("2 new, 0 changed, 1 orphaned, 0 unchanged"));
+
+    mirror_dir.child("input1.wav").assert(predicate::path::exists());
+    mirror_dir.child("a").child("input2.wav").assert(predicate::path::exists());
+    mirror_dir.child("b").child("orphan.wav").assert(predicate::path::exists());
+
+    std::fs::remove_file(source_dir.child("input1.mp3"))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["sync", "-m", "mp3=wav", &source_dir.to_string_lossy(), "-o", &mirror_dir.to_string_lossy(), "--delete"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 new, 0 changed, 2 orphaned, 1 unchanged"));
+
+    mirror_dir.child("input1.wav").assert(predicate::path::missing());
+    mirror_dir.child("b").assert(predicate::path::missing());
+    mirror_dir.child("a").child("input2.wav").assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn watch_converts_new_files() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    let child = Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy(), "--watch", "--settle-time", "0.5"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+    input_dir.child("a").child("input2.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("ignored.txt").touch()?;
+    std::thread::sleep(std::time::Duration::from_millis(2500));
+
+    Command::new("kill").args(["-INT", "--", &format!("-{}", child.id())]).assert().success();
+    let output = child.wait_with_output()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 files converted, 0 cancelled"), "{stderr}");
+
+    let input_dir_name = input_dir.file_name().unwrap();
+    output_dir.child(input_dir_name).child("input1.wav").assert(predicate::path::exists());
+    output_dir.child(input_dir_name).child("a").child("input2.wav").assert(predicate::path::exists());
+    output_dir.child(input_dir_name).child("ignored.wav").assert(predicate::path::missing());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn watch_reconverts_modified_files() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("a.txt").write_str("first")?;
+
+    let child = Command::cargo_bin(BIN_NAME)?
+        .args(["-y", "-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "txt=md@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "cp {{input}} {{output}}", "--watch", "--settle-time", "0.5"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+    input_dir.child("a.txt").write_str("second")?;
+    std::thread::sleep(std::time::Duration::from_millis(2500));
+
+    Command::new("kill").args(["-INT", "--", &format!("-{}", child.id())]).assert().success();
+    let output = child.wait_with_output()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 files converted, 0 cancelled"), "{stderr}");
+    output_dir.child("a.md").assert("second");
+
+    Ok(())
+}
+
+#[test]
+fn metadata_placeholders() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    let pattern = output_dir.child("{{codec}}").child("{{tag:lconvert-missing?No Tag}}").child("{{tag:lconvert-missing}}-{{stem}}");
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .success();
+
+    output_dir.child("mp3").child("No Tag").child("Unknown-input1.wav").assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn placeholder_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("My Song (Live).mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    let pattern = output_dir.child("{{tag:lconvert-missing|default:unknown}}").child("{{stem|slug}}-{{stem|replace: ,_|upper|trunc:7}}");
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &pattern.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .success();
+
+    output_dir.child("unknown").child("my-song-live-MY_SONG.wav").assert(predicate::path::exists());
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem|reverse}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown filter 'reverse'"));
+
+    Ok(())
+}
+
+#[test]
+fn index_date_hash_placeholders() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("b").child("DSC0001.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("a").child("DSC0001.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    let hash = blake3::hash(&std::fs::read(get_test_file!(TEST_FILE_MP3))?).to_hex();
+    let date = chrono::DateTime::<chrono::Local>::from(input_dir.child("a").child("DSC0001.mp3").metadata()?.modified()?).format("%Y-%m-%d");
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{date}}_{{index:03}}_{{hash:6}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .success();
+
+    let mut outputs = read_dir!(output_dir);
+    outputs.sort();
+    assert_eq!(outputs, vec![
+        PathBuf::from(format!("{date}_001_{}.wav", &hash[..6])),
+        PathBuf::from(format!("{date}_002_{}.wav", &hash[..6])),
+    ]);
+
+    Ok(())
+}
+
+#[test]
+fn pattern_validation() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{fiel}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("did you mean '{{file}}'"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unclosed"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{index:100000}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("at most 10"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{date:%Q}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid date format: '%Q'"));
+
+    assert!(read_dir!(output_dir).is_empty());
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{{{raw}}}}").child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .assert()
+        .success();
+
+    output_dir.child("{{raw}}").child("input.wav").assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn placeholders_in_ffmpeg_options() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .args(["--", "-metadata", "title={{stem|upper}}", "-metadata", "comment={{codec}} from {{file}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("title=INPUT1"))
+        .stdout(predicate::str::contains("comment=mp3 from input1.mp3"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy(), "--", "-metadata", "title={{stme}}"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("did you mean '{{stem}}'"));
+
+    Ok(())
+}
+
+#[test]
+fn per_rule_ffmpeg_options() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("input2.OGG").write_file(get_test_file!(TEST_FILE_OGG))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav[-ar 22050 -metadata 'title=a b'],ogg=flac[-af volume=0.5,aresample=44100]"])
+        .args(["--opts", "wav:-ac 1", &input_dir.to_string_lossy(), "--", "-vn"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-vn -ac 1 -ar 22050 -metadata 'title=a b'"))
+        .stdout(predicate::str::contains("-vn -af volume=0.5,aresample=44100"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "mp3=wav[-ar 22050", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unbalanced brackets"));
+
+    Ok(())
+}
+
+#[test]
+fn input_options() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    let input_file = input_dir.child("input.mp3");
+    input_file.write_file(get_test_file!(TEST_FILE_MP3))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", "--input-opts", "-ss 3 -t 2", &input_file.to_string_lossy(), "--", "-ac", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("-ss 3 -t 2 -i {} -ac 1", input_file.display())));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", "--input-opts", "-ss 3", &input_file.to_string_lossy()])
+        .assert()
+        .success();
+
+    output_dir.child("input.wav").assert(predicate::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn command_templates() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("input2.OGG").write_file(get_test_file!(TEST_FILE_OGG))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav,ogg=flac", &input_dir.to_string_lossy()])
+        .args(["--template", "ffmpeg -i {{input}} -af volume=0.5 -metadata title={{stem}} {{output}}"])
+        .args(["--template", "ogg:ffmpeg -i {{input}} -map 0:a {{output}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-progress - -nostats -i"))
+        .stdout(predicate::str::contains("input1.mp3 -af volume=0.5 -metadata title=input1"))
+        .stdout(predicate::str::contains("input2.OGG -map 0:a"));
+
+    // keyed by rule, ignoring case, the program is dropped by its name
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav+flac", &input_dir.to_string_lossy()])
+        .args(["--template", "MP3=FLAC:/usr/bin/ffmpeg -i {{input}} -c:a flac {{output}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("input1.mp3 -c:a flac").count(1))
+        .stdout(predicate::str::contains("/usr/bin/ffmpeg").not());
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .args(["--template", "ogg:ffmpeg -i {{input}} {{output}}"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Template for 'ogg' matches no extension map rule"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "cp {{input}} {{output}}", "--template", "mp3:ffmpeg -i {{input}} {{output}}"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Template for 'mp3' matches only rules that are not converted with ffmpeg: 'mp3=wav'"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy()])
+        .args(["--template", "ffmpeg -i {{input}} -ac 1 {{output}}"])
+        .assert()
+        .success();
+
+    output_dir.child("input1.wav").assert(predicate::path::exists());
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "mp3=wav", &input_dir.to_string_lossy(), "--template", "ffmpeg -i {{input}}"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("has no '{{output}}'"));
+
+    Ok(())
+}
+
+#[test]
+fn converter_backends() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("notes.txt").write_str("some notes")?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav,txt=md@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "cp {{input}} {{output}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-progress - -nostats -i"))
+        .stdout(predicate::str::is_match(r"cp \S*notes\.txt \S*\.notes\.\d+\.lconvert-tmp\.md")?);
+
+    // ffmpeg is not needed when no job uses it
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "txt=md@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "txt:cp {{input}} {{output}}", "--ffmpeg-path", "no-such-ffmpeg"])
+        .assert()
+        .success();
+
+    output_dir.child("notes.md").assert("some notes");
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "txt=md@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "cp {{input}} {{output}}", "--ffmpeg-path", "no-such-ffmpeg"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"cp \S*notes\.txt \S*\.notes\.\d+\.lconvert-tmp\.md")?);
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "txt=md@cmd", &input_dir.to_string_lossy()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("needs a command"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "txt=md@cmd+html@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "cp {{input}} {{output}}", "--cmd", "TXT=HTML:ln -s {{input}} {{output}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"cp \S*notes\.txt \S*\.md")?)
+        .stdout(predicate::str::is_match(r"ln -s \S*notes\.txt \S*\.html")?);
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.to_string_lossy(), "-m", "mp3=wav,txt=md@cmd", &input_dir.to_string_lossy()])
+        .args(["--cmd", "cp {{input}} {{output}}", "--cmd", "mp3:cp {{input}} {{output}}"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Command for 'mp3' matches no extension map rule using the 'cmd' converter"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "docx=pdf@sofice", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("did you mean 'soffice'?"));
+
+    Ok(())
+}
+
+#[test]
+fn config_presets() -> Result<(), Box<dyn std::error::Error>> {
+    let work_dir = assert_fs::TempDir::new()?;
+    let home_dir = assert_fs::TempDir::new()?;
+
+    work_dir.child("in").child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    work_dir.child("in").child("input2.OGG").write_file(get_test_file!(TEST_FILE_OGG))?;
+    home_dir.child(".config/lconvert/lconvert.toml").write_str(
+        "[presets.web]\nextension-map = \"ogg=flac\"\n\n[presets.user]\nextension-map = \"mp3=flac\"\noutput = \"user/{{stem}}\"\n",
+    )?;
+    work_dir.child("lconvert.toml").write_str(
+        "[presets.web]\nextension-map = \"mp3=wav\"\noutput = \"out/{{stem}}\"\noptions = [\"-ac\", \"1\"]\nopts = [\"wav:-ar 8000\"]\nallow-override = true\n",
+    )?;
+
+    let lconvert = || -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin(BIN_NAME)?;
+        cmd.current_dir(&work_dir).env("HOME", home_dir.path()).env_remove("XDG_CONFIG_HOME");
+        Ok(cmd)
+    };
+
+    lconvert()?
+        .args(["--preset", "web", "--dry-run", "in"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("input1.mp3 -ac 1 -ar 8000"))
+        .stdout(predicate::str::is_match(r"out/\.input1\.\d+\.lconvert-tmp\.wav")?)
+        .stdout(predicate::str::contains("1 planned, 1 skipped"));
+
+    lconvert()?
+        .args(["--preset", "web", "--dry-run", "-m", "mp3=flac", "-o", "cli/{{stem}}", "in", "--", "-ac", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("input1.mp3 -ac 2 "))
+        .stdout(predicate::str::is_match(r"cli/\.input1\.\d+\.lconvert-tmp\.flac")?);
+
+    lconvert()?
+        .args(["--preset", "user", "--dry-run", "in"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"user/\.input1\.\d+\.lconvert-tmp\.flac")?);
+
+    // flags of a preset can be turned off
+    work_dir.child("out").child("input1.wav").touch()?;
+    lconvert()?
+        .args(["--preset", "web", "--no-allow-override", "in"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("already exists"));
+
+    lconvert()?
+        .args(["--preset", "web", "in"])
+        .assert()
+        .success();
+
+    lconvert()?
+        .args(["--preset", "wbe", "in"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("did you mean 'web'?"));
+
+    work_dir.child("lconvert.toml").write_str("[presets.web]\nextension_map = \"mp3=wav\"\n")?;
+    lconvert()?
+        .args(["--preset", "web", "in"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("unknown field `extension_map`"));
+
+    Ok(())
+}
+
+#[test]
+fn builtin_presets() -> Result<(), Box<dyn std::error::Error>> {
+    let work_dir = assert_fs::TempDir::new()?;
+
+    work_dir.child("in").child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    work_dir.child("lconvert.toml").write_str("[presets.podcast-mp3]\ndescription = \"Ours\"\nextension-map = \"mp3=ogg\"\n")?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .current_dir(&work_dir)
+        .env("HOME", work_dir.path())
+        .args(["presets"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("web-mp4 (built-in)"))
+        .stdout(predicate::str::contains("-movflags +faststart"))
+        .stdout(predicate::str::contains("whatsapp (built-in)"))
+        .stdout(predicate::str::contains("podcast-mp3 (lconvert.toml)\n  Ours\n  lconvert -m mp3=ogg"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .current_dir(&work_dir)
+        .env("HOME", work_dir.path())
+        .args(["--preset", "archive-flac", "--dry-run", "-m", "mp3=flac", "in"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("input1.mp3 -c:a flac -compression_level 8"));
+
+    Ok(())
+}
+
+#[test]
+fn multiple_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("input2.OGG").write_file(get_test_file!(TEST_FILE_OGG))?;
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["--dry-run", "-o", &output_dir.child("{{index}}-{{stem}}.{{out-ext}}").to_string_lossy(), &input_dir.to_string_lossy()])
+        .args(["-m", "mp3=wav[-ac 1]+flac,ogg=mp3", "--opts", "flac:-compression_level 8"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("input1.mp3 -ac 1 "))
+        .stdout(predicate::str::is_match(r"1-input1\.\d+\.lconvert-tmp\.wav")?)
+        .stdout(predicate::str::contains("input1.mp3 -compression_level 8 "))
+        .stdout(predicate::str::is_match(r"1-input1\.\d+\.lconvert-tmp\.flac")?)
+        .stdout(predicate::str::is_match(r"2-input2\.\d+\.lconvert-tmp\.mp3")?)
+        .stdout(predicate::str::contains("3 planned, 0 skipped"));
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.child("{{stem}}").to_string_lossy(), "-m", "mp3=wav+flac", &input_dir.to_string_lossy()])
+        .assert()
+        .success();
+
+    output_dir.child("input1.wav").assert(predicate::path::exists());
+    output_dir.child("input1.flac").assert(predicate::path::exists());
+
+    Command::cargo_bin(BIN_NAME)?
+        .args(["-o", &output_dir.to_string_lossy(), "-m", "mp3=wav+WAV+wav", &input_dir.to_string_lossy()])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("appears twice"));
+
+    Ok(())
+}
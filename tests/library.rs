@@ -0,0 +1,93 @@
+use assert_fs::prelude::*;
+use std::path::{Path, PathBuf};
+use lconvert::{init_ffmpeg_paths, ExtensionMap, MediaInfo, OutputPattern, Planner, Runner, StreamKind};
+
+static TEST_FILE_MP3: &str = "input.mp3";
+
+macro_rules! get_test_file {($fname:expr) => (
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("resources").join($fname).as_path()
+)}
+
+#[test]
+fn plan_and_run() -> Result<(), Box<dyn std::error::Error>> {
+    init_ffmpeg_paths(Path::new("ffmpeg"), Path::new("ffprobe"))?;
+
+    let input_dir = assert_fs::TempDir::new()?;
+    let output_dir = assert_fs::TempDir::new()?;
+
+    input_dir.child("a").child("input1.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("input2.mp3").write_file(get_test_file!(TEST_FILE_MP3))?;
+    input_dir.child("ignored.txt").touch()?;
+
+    let planner = Planner::new(
+        OutputPattern::new(output_dir.join("{{stem}}.{{out-ext}}"))?,
+        ExtensionMap::from([("mp3".to_owned(), vec!["wav".to_owned()])]),
+    );
+
+    let plan = planner.plan(vec![input_dir.to_path_buf()])?;
+    let mut jobs = plan.jobs;
+    jobs.sort_by(|a, b| a.output_file.cmp(&b.output_file));
+
+    assert_eq!(
+        jobs.iter().map(|x| x.output_file.clone()).collect::<Vec<_>>(),
+        vec![output_dir.join("input1.wav"), output_dir.join("input2.wav")]
+    );
+
+    // durations are probed by the runner, concurrently with the conversions
+    assert!(jobs.iter().all(|x| x.duration.is_none()));
+
+    assert_eq!(plan.skipped.len(), 1);
+    assert_eq!(plan.skipped[0].input_file, input_dir.join("ignored.txt"));
+
+    let outcome = Runner::new(2).run(jobs)?;
+
+    assert_eq!(outcome.completed.len(), 2);
+    assert_eq!(outcome.n_succeeded(), 2);
+    assert!(outcome.not_started.is_empty());
+    assert!(outcome.completed.iter().all(|x| x.options.duration.is_some()));
+    assert!(outcome.completed.iter().all(|x| x.options.media_info.as_ref().is_some_and(|x| x.audio().is_some())));
+    output_dir.child("input1.wav").assert(predicates::path::exists());
+    output_dir.child("input2.wav").assert(predicates::path::exists());
+
+    Ok(())
+}
+
+#[test]
+fn media_info_from_ffprobe_json() -> Result<(), Box<dyn std::error::Error>> {
+    let info = MediaInfo::from_json(r#"{
+        "streams": [
+            {
+                "index": 0, "codec_name": "h264", "codec_type": "video", "width": 1920, "height": 1080,
+                "avg_frame_rate": "30000/1001", "bit_rate": "4000000", "duration": "12.012000", "tags": { "language": "und" }
+            },
+            {
+                "index": 1, "codec_name": "opus", "codec_type": "audio", "sample_rate": "48000", "channels": 2,
+                "avg_frame_rate": "0/0", "tags": { "ARTIST": "Someone" }
+            }
+        ],
+        "format": {
+            "format_name": "matroska,webm", "duration": "12.040000", "bit_rate": "4200000",
+            "tags": { "TITLE": "A title", "ENCODER": "Lavf" }
+        }
+    }"#)?;
+
+    assert_eq!(info.container.as_deref(), Some("matroska,webm"));
+    assert_eq!(info.duration, Some(12.04));
+    assert_eq!(info.bit_rate, Some(4_200_000));
+    assert_eq!(info.streams.len(), 2);
+
+    let video = info.video().unwrap();
+    assert_eq!((video.codec.as_deref(), video.width, video.height), (Some("h264"), Some(1920), Some(1080)));
+    assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.01);
+    assert_eq!(info.frame_count(), Some(360));
+
+    let audio = info.first_stream(StreamKind::Audio).unwrap();
+    assert_eq!((audio.sample_rate, audio.channels, audio.frame_rate), (Some(48000), Some(2), None));
+
+    // container tags first, then stream tags, case insensitive
+    assert_eq!(info.tag("title"), Some("A title"));
+    assert_eq!(info.tag("Artist"), Some("Someone"));
+    assert_eq!(info.tag("album"), None);
+
+    Ok(())
+}